@@ -0,0 +1,319 @@
+use core::fmt::Formatter;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::devices::calibration::{
+    get_calibration_boiling_point, Calibration, ICE_POINT, SENSOR_CALIBRATIONS_KEY,
+};
+use crate::devices::ds18b20::Ds18b20;
+use crate::devices::sensor_error::SensorError;
+use crate::devices::temperature_source::TemperatureSource;
+use crate::helpers::{parse_key_value_list, update_config_value};
+
+const CALIBRATION_READING_COUNT: usize = 10;
+/// The readings averaged for a reference must all be this close, so the probe had
+/// time to reach the water temperature.
+const CALIBRATION_SETTLED_SPREAD: f32 = 0.25;
+const CALIBRATION_MAXIMUM_READINGS: usize = 300;
+const CALIBRATION_READING_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq)]
+pub enum CalibrationError {
+    NoProbe,
+    UnknownProbe(String),
+    SeveralProbes(Vec<String>),
+    Sensor(SensorError),
+    Io(String),
+    InvertedReferences(f32, f32),
+    Unsettled(f32),
+}
+
+impl Display for CalibrationError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CalibrationError::NoProbe => write!(formatter, "No probe found"),
+            CalibrationError::UnknownProbe(serial) => {
+                write!(formatter, "No probe with serial {}", serial)
+            }
+            CalibrationError::SeveralProbes(serials) => write!(
+                formatter,
+                "Several probes found, pick one of: {}",
+                serials.join(", ")
+            ),
+            CalibrationError::Sensor(err) => write!(formatter, "Sensor error: {}", err),
+            CalibrationError::Io(message) => write!(formatter, "I/O error: {}", message),
+            CalibrationError::InvertedReferences(ice_reading, boiling_reading) => write!(
+                formatter,
+                "Boiling water read {}C, not above ice water at {}C",
+                boiling_reading, ice_reading
+            ),
+            CalibrationError::Unsettled(spread) => write!(
+                formatter,
+                "The reading never settled, still moving by {:.3}C",
+                spread
+            ),
+        }
+    }
+}
+
+impl From<SensorError> for CalibrationError {
+    fn from(err: SensorError) -> Self {
+        CalibrationError::Sensor(err)
+    }
+}
+
+impl From<std::io::Error> for CalibrationError {
+    fn from(err: std::io::Error) -> Self {
+        CalibrationError::Io(err.to_string())
+    }
+}
+
+/// Averages the last `CALIBRATION_READING_COUNT` readings once they agree.
+fn average_reading(
+    source: &mut dyn TemperatureSource,
+    period: Duration,
+) -> Result<f32, CalibrationError> {
+    let mut readings = Vec::with_capacity(CALIBRATION_MAXIMUM_READINGS);
+    let mut spread = f32::MAX;
+    while readings.len() < CALIBRATION_MAXIMUM_READINGS {
+        if !readings.is_empty() {
+            sleep(period);
+        }
+        readings.push(source.read()?.temperature);
+        if readings.len() < CALIBRATION_READING_COUNT {
+            continue;
+        }
+        let latest = &readings[readings.len() - CALIBRATION_READING_COUNT..];
+        let lowest = latest.iter().copied().fold(f32::MAX, f32::min);
+        let highest = latest.iter().copied().fold(f32::MIN, f32::max);
+        spread = highest - lowest;
+        if spread <= CALIBRATION_SETTLED_SPREAD {
+            return Ok(latest.iter().sum::<f32>() / CALIBRATION_READING_COUNT as f32);
+        }
+    }
+    Err(CalibrationError::Unsettled(spread))
+}
+
+fn prompt<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    message: &str,
+) -> Result<String, CalibrationError> {
+    writeln!(output, "{}", message)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+}
+
+/// Walks the user through the ice water and boiling water references of one probe.
+pub fn calibrate_probe<R: BufRead, W: Write>(
+    source: &mut dyn TemperatureSource,
+    input: &mut R,
+    output: &mut W,
+    boiling_point: f32,
+    period: Duration,
+) -> Result<Calibration, CalibrationError> {
+    writeln!(output, "Calibrating probe {}", source.identity())?;
+    prompt(
+        input,
+        output,
+        "Put the probe in stirred ice water, wait for the reading to settle, then press Enter",
+    )?;
+    let ice_reading = average_reading(source, period)?;
+    writeln!(output, "Ice water reads {:.3}C", ice_reading)?;
+
+    let answer = prompt(
+        input,
+        output,
+        &format!(
+            "Put the probe in boiling water ({}C here, see CALIBRATION_BOILING_POINT) and press Enter, the reading is taken once it settles, or type skip to only correct the offset",
+            boiling_point
+        ),
+    )?;
+    if answer == "skip" {
+        return Ok(Calibration::Offset(ICE_POINT - ice_reading));
+    }
+    let boiling_reading = average_reading(source, period)?;
+    writeln!(output, "Boiling water reads {:.3}C", boiling_reading)?;
+    if boiling_reading <= ice_reading {
+        return Err(CalibrationError::InvertedReferences(
+            ice_reading,
+            boiling_reading,
+        ));
+    }
+
+    Ok(Calibration::TwoPoint {
+        ice_reading,
+        boiling_reading,
+        boiling_point,
+    })
+}
+
+/// Runs `calibrate [serial]` against the probes on the bus and saves the result
+/// to `SENSOR_CALIBRATIONS` in the configuration file.
+pub fn run_calibration(
+    w1_devices_dir: &Path,
+    config_path: &Path,
+    serial: Option<String>,
+) -> Result<(), CalibrationError> {
+    let mut probes = Ds18b20::discover(w1_devices_dir).map_err(|_| CalibrationError::NoProbe)?;
+    let index = match serial {
+        Some(serial) => probes
+            .iter()
+            .position(|probe| probe.identity() == serial)
+            .ok_or(CalibrationError::UnknownProbe(serial))?,
+        None if probes.len() == 1 => 0,
+        None => {
+            return Err(CalibrationError::SeveralProbes(
+                probes.iter().map(|probe| probe.identity()).collect(),
+            ))
+        }
+    };
+    let probe = &mut probes[index];
+
+    let stdin = std::io::stdin();
+    let calibration = calibrate_probe(
+        probe,
+        &mut stdin.lock(),
+        &mut std::io::stdout(),
+        get_calibration_boiling_point(),
+        CALIBRATION_READING_PERIOD,
+    )?;
+
+    let mut calibrations: BTreeMap<String, String> =
+        parse_key_value_list(&env::var(SENSOR_CALIBRATIONS_KEY).unwrap_or_default())
+            .into_iter()
+            .collect();
+    calibrations.insert(probe.identity(), calibration.to_string());
+    let raw_calibrations: Vec<String> = calibrations
+        .iter()
+        .map(|(serial, calibration)| format!("{}={}", serial, calibration))
+        .collect();
+    update_config_value(
+        config_path,
+        SENSOR_CALIBRATIONS_KEY,
+        &raw_calibrations.join(","),
+    )?;
+    println!(
+        "Saved calibration {} for {} to {}",
+        calibration,
+        probe.identity(),
+        config_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::temperature_source::{MockTemperatureSource, TemperatureReading};
+    use std::io::Cursor;
+
+    fn source_reading(temperatures: Vec<f32>) -> MockTemperatureSource {
+        let mut source = MockTemperatureSource::new();
+        let mut temperatures = temperatures.into_iter();
+        source
+            .expect_identity()
+            .returning(|| "28-0000000000aa".to_string());
+        source
+            .expect_read()
+            .returning(move || Ok(TemperatureReading::new(temperatures.next().unwrap())));
+        source
+    }
+
+    #[test]
+    fn calibrate_probe_uses_both_references() {
+        let mut readings = vec![0.5; CALIBRATION_READING_COUNT];
+        readings.extend(vec![99.0; CALIBRATION_READING_COUNT]);
+        let mut source = source_reading(readings);
+        let mut output = Vec::new();
+
+        let calibration = calibrate_probe(
+            &mut source,
+            &mut Cursor::new("\n\n"),
+            &mut output,
+            100.0,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        assert_eq!(
+            calibration,
+            Calibration::TwoPoint {
+                ice_reading: 0.5,
+                boiling_reading: 99.0,
+                boiling_point: 100.0,
+            }
+        );
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Boiling water reads 99.000C"));
+    }
+
+    #[test]
+    fn calibrate_probe_can_skip_the_boiling_reference() {
+        let mut source = source_reading(vec![-0.25; CALIBRATION_READING_COUNT]);
+
+        let calibration = calibrate_probe(
+            &mut source,
+            &mut Cursor::new("\nskip\n"),
+            &mut Vec::new(),
+            100.0,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        assert_eq!(calibration, Calibration::Offset(0.25));
+    }
+
+    #[test]
+    fn calibrate_probe_waits_for_the_reading_to_settle() {
+        let mut readings = vec![0.5; CALIBRATION_READING_COUNT];
+        readings.extend([20.0, 60.0, 90.0, 97.0, 98.5]);
+        readings.extend(vec![99.0; CALIBRATION_READING_COUNT]);
+        let mut source = source_reading(readings);
+
+        let calibration = calibrate_probe(
+            &mut source,
+            &mut Cursor::new("\n\n"),
+            &mut Vec::new(),
+            100.0,
+            Duration::ZERO,
+        )
+        .unwrap();
+
+        assert_eq!(
+            calibration,
+            Calibration::TwoPoint {
+                ice_reading: 0.5,
+                boiling_reading: 99.0,
+                boiling_point: 100.0,
+            }
+        );
+    }
+
+    #[test]
+    fn calibrate_probe_gives_up_on_a_moving_reading() {
+        let mut source = source_reading(
+            (0..CALIBRATION_MAXIMUM_READINGS)
+                .map(|index| index as f32)
+                .collect(),
+        );
+
+        let result = calibrate_probe(
+            &mut source,
+            &mut Cursor::new("\n"),
+            &mut Vec::new(),
+            100.0,
+            Duration::ZERO,
+        );
+
+        assert_eq!(result, Err(CalibrationError::Unsettled(9.0)));
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::Path;
+
+use log::{error, info};
+
+static CONSUMED_COMMAND_FILE: &str = "consumed";
+static PROFILE_COMMAND_FILE: &str = "profile";
+static ACKNOWLEDGE_COMMAND_FILE: &str = "acknowledge";
+
+#[cfg(not(debug_assertions))]
+pub static COMMANDS_PATH: &str = "/var/lib/baby_bottle/commands/";
+
+#[cfg(debug_assertions)]
+pub static COMMANDS_PATH: &str = "commands/";
+
+/// Requests sent from the command line to the running monitor.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// The prepared bottle was used; `None` applies to every sensor.
+    Consumed(Option<String>),
+    /// Prepare the next bottle with the named profile; `None` applies to every sensor.
+    Profile(String, Option<String>),
+    /// Someone saw the alerts, stop escalating them; `None` applies to every sensor.
+    Acknowledge(Option<String>),
+}
+
+pub fn send_command(commands_directory: &Path, command: &Command) -> std::io::Result<()> {
+    fs::create_dir_all(commands_directory)?;
+    match command {
+        Command::Consumed(sensor_name) => fs::write(
+            commands_directory.join(CONSUMED_COMMAND_FILE),
+            sensor_name.clone().unwrap_or_default(),
+        ),
+        Command::Profile(profile_name, sensor_name) => fs::write(
+            commands_directory.join(PROFILE_COMMAND_FILE),
+            format!(
+                "{}\n{}",
+                profile_name,
+                sensor_name.clone().unwrap_or_default()
+            ),
+        ),
+        Command::Acknowledge(sensor_name) => fs::write(
+            commands_directory.join(ACKNOWLEDGE_COMMAND_FILE),
+            sensor_name.clone().unwrap_or_default(),
+        ),
+    }
+}
+
+/// Reads and removes a command file, `None` when there is no such pending command.
+fn take_command_file(commands_directory: &Path, command_file: &str) -> Option<String> {
+    let command_filepath = commands_directory.join(command_file);
+    let content = fs::read_to_string(&command_filepath).ok()?;
+    if let Err(err) = fs::remove_file(&command_filepath) {
+        error!("Unable to clear the {} command: {}", command_file, err);
+    }
+    Some(content)
+}
+
+fn optional_sensor_name(sensor_name: &str) -> Option<String> {
+    let sensor_name = sensor_name.trim();
+    if sensor_name.is_empty() {
+        None
+    } else {
+        Some(sensor_name.to_string())
+    }
+}
+
+/// Returns the pending commands and removes them so each one is applied once.
+pub fn take_commands(commands_directory: &Path) -> Vec<Command> {
+    let mut commands = Vec::new();
+    if let Some(content) = take_command_file(commands_directory, PROFILE_COMMAND_FILE) {
+        let mut lines = content.lines();
+        let profile_name = lines.next().unwrap_or_default().trim().to_string();
+        let sensor_name = optional_sensor_name(lines.next().unwrap_or_default());
+        info!(
+            "Received profile command {} for {:?}",
+            profile_name, sensor_name
+        );
+        if !profile_name.is_empty() {
+            commands.push(Command::Profile(profile_name, sensor_name));
+        }
+    }
+    if let Some(content) = take_command_file(commands_directory, CONSUMED_COMMAND_FILE) {
+        let sensor_name = optional_sensor_name(&content);
+        info!("Received consumed command for {:?}", sensor_name);
+        commands.push(Command::Consumed(sensor_name));
+    }
+    if let Some(content) = take_command_file(commands_directory, ACKNOWLEDGE_COMMAND_FILE) {
+        let sensor_name = optional_sensor_name(&content);
+        info!("Received acknowledge command for {:?}", sensor_name);
+        commands.push(Command::Acknowledge(sensor_name));
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn commands_are_taken_once() {
+        let commands_directory = TempDir::new().unwrap();
+        send_command(
+            commands_directory.path(),
+            &Command::Consumed(Some("left".to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            take_commands(commands_directory.path()),
+            vec![Command::Consumed(Some("left".to_string()))]
+        );
+        assert_eq!(take_commands(commands_directory.path()), vec![]);
+    }
+
+    #[test]
+    fn consumed_without_sensor_applies_to_all() {
+        let commands_directory = TempDir::new().unwrap();
+        send_command(commands_directory.path(), &Command::Consumed(None)).unwrap();
+
+        assert_eq!(
+            take_commands(commands_directory.path()),
+            vec![Command::Consumed(None)]
+        );
+    }
+
+    #[test]
+    fn profile_command_carries_the_profile_and_sensor() {
+        let commands_directory = TempDir::new().unwrap();
+        send_command(
+            commands_directory.path(),
+            &Command::Profile("night".to_string(), Some("left".to_string())),
+        )
+        .unwrap();
+        send_command(commands_directory.path(), &Command::Consumed(None)).unwrap();
+
+        assert_eq!(
+            take_commands(commands_directory.path()),
+            vec![
+                Command::Profile("night".to_string(), Some("left".to_string())),
+                Command::Consumed(None)
+            ]
+        );
+    }
+
+    #[test]
+    fn acknowledge_command_carries_the_sensor() {
+        let commands_directory = TempDir::new().unwrap();
+        send_command(
+            commands_directory.path(),
+            &Command::Acknowledge(Some("left".to_string())),
+        )
+        .unwrap();
+
+        assert_eq!(
+            take_commands(commands_directory.path()),
+            vec![Command::Acknowledge(Some("left".to_string()))]
+        );
+    }
+}
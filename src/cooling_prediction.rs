@@ -0,0 +1,245 @@
+use chrono::{DateTime, Duration, Utc};
+
+use crate::cooling_rate::{elapsed_seconds, linear_regression};
+
+const MINIMUM_SAMPLES: usize = 10;
+const MINIMUM_SPAN_IN_SECONDS: f64 = 30.0;
+const AMBIENT_SEARCH_STEP: f64 = 0.25;
+const AMBIENT_SEARCH_RANGE: f64 = 60.0;
+const LOWEST_AMBIENT_TEMPERATURE: f64 = -10.0;
+
+/// Newton's law of cooling, `T(t) = ambient + initial_difference * exp(-k * t)`,
+/// with `t` counted in seconds from `origin`. The initial difference is negative
+/// when the bottle warms up toward a hotter bath.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NewtonCoolingFit {
+    pub ambient: f64,
+    pub cooling_constant: f64,
+    initial_difference: f64,
+    origin: DateTime<Utc>,
+}
+
+impl NewtonCoolingFit {
+    fn temperature_after(&self, seconds: f64) -> f64 {
+        self.ambient + self.initial_difference * (-self.cooling_constant * seconds).exp()
+    }
+
+    /// Time left from `now` until the curve crosses `target`, or `None` when the
+    /// water settles at an ambient temperature short of the target.
+    pub fn time_to_reach(&self, target: f32, now: DateTime<Utc>) -> Option<Duration> {
+        let ratio = self.initial_difference / (target as f64 - self.ambient);
+        if ratio <= 0.0 || !ratio.is_finite() {
+            return None;
+        }
+
+        let crossing = ratio.ln() / self.cooling_constant;
+        let remaining = crossing - self.seconds_since_origin(now);
+        if remaining <= 0.0 {
+            return Some(Duration::zero());
+        }
+        Some(Duration::milliseconds((remaining * 1000.0) as i64))
+    }
+
+    fn seconds_since_origin(&self, timestamp: DateTime<Utc>) -> f64 {
+        timestamp
+            .signed_duration_since(self.origin)
+            .num_milliseconds() as f64
+            / 1000.0
+    }
+}
+
+/// Fits a cooling curve by trying ambient temperatures beyond the coldest sample
+/// (or the hottest one, when the samples are warming up) and keeping the one whose
+/// log-linear fit has the smallest squared error.
+pub fn fit_newton_cooling(samples: &[(DateTime<Utc>, f32)]) -> Option<NewtonCoolingFit> {
+    if samples.len() < MINIMUM_SAMPLES {
+        return None;
+    }
+    let origin = samples.first()?.0;
+    let points = elapsed_seconds(samples);
+    let span = points.last()?.0 - points.first()?.0;
+    if span < MINIMUM_SPAN_IN_SECONDS {
+        return None;
+    }
+
+    let warming = points.last()?.1 > points.first()?.1;
+    let candidate_ambients: Vec<f64> = if warming {
+        let hottest = points
+            .iter()
+            .map(|(_, temperature)| *temperature)
+            .fold(f64::NEG_INFINITY, f64::max);
+        ambient_steps(hottest, hottest + AMBIENT_SEARCH_RANGE, AMBIENT_SEARCH_STEP)
+    } else {
+        let coldest = points
+            .iter()
+            .map(|(_, temperature)| *temperature)
+            .fold(f64::INFINITY, f64::min);
+        let lowest_ambient = (coldest - AMBIENT_SEARCH_RANGE).max(LOWEST_AMBIENT_TEMPERATURE);
+        ambient_steps(coldest, lowest_ambient, -AMBIENT_SEARCH_STEP)
+    };
+
+    let mut best_fit: Option<(f64, NewtonCoolingFit)> = None;
+    for ambient in candidate_ambients {
+        if let Some(candidate) = fit_for_ambient(&points, ambient, origin) {
+            let squared_error: f64 = points
+                .iter()
+                .map(|(time, temperature)| {
+                    (temperature - candidate.temperature_after(*time)).powi(2)
+                })
+                .sum();
+            if best_fit.is_none_or(|(best_error, _)| squared_error < best_error) {
+                best_fit = Some((squared_error, candidate));
+            }
+        }
+    }
+
+    best_fit.map(|(_, fit)| fit)
+}
+
+/// Ambient candidates one `step` past `extreme`, up to and including `limit`.
+fn ambient_steps(extreme: f64, limit: f64, step: f64) -> Vec<f64> {
+    let count = ((limit - extreme) / step).floor().max(0.0) as usize;
+    (1..=count)
+        .map(|index| extreme + step * index as f64)
+        .collect()
+}
+
+fn fit_for_ambient(
+    points: &[(f64, f64)],
+    ambient: f64,
+    origin: DateTime<Utc>,
+) -> Option<NewtonCoolingFit> {
+    let direction = if points.iter().all(|(_, temperature)| *temperature > ambient) {
+        1.0
+    } else {
+        -1.0
+    };
+    let log_points: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(time, temperature)| (*time, (direction * (temperature - ambient)).ln()))
+        .collect();
+    let fit = linear_regression(&log_points)?;
+    if fit.slope >= 0.0 || !fit.slope.is_finite() || !fit.intercept.is_finite() {
+        return None;
+    }
+
+    Some(NewtonCoolingFit {
+        ambient,
+        cooling_constant: -fit.slope,
+        initial_difference: direction * fit.intercept.exp(),
+        origin,
+    })
+}
+
+/// Human wording for an ETA, e.g. "about 12 minutes left".
+pub fn format_time_left(time_left: Duration) -> String {
+    let minutes = (time_left.num_seconds() as f64 / 60.0).round() as i64;
+    match minutes {
+        0 => String::from("less than a minute left"),
+        1 => String::from("about 1 minute left"),
+        _ => format!("about {} minutes left", minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMBIENT: f64 = 22.0;
+    const INITIAL_DIFFERENCE: f64 = 60.0;
+    const COOLING_CONSTANT: f64 = 0.002;
+
+    fn cooling_curve(seconds: &[i64]) -> Vec<(DateTime<Utc>, f32)> {
+        seconds
+            .iter()
+            .map(|second| {
+                let temperature =
+                    AMBIENT + INITIAL_DIFFERENCE * (-COOLING_CONSTANT * *second as f64).exp();
+                (
+                    DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(*second),
+                    temperature as f32,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fit_newton_cooling_recovers_the_curve() {
+        let samples = cooling_curve(&(0..300).step_by(5).collect::<Vec<i64>>());
+
+        let fit = fit_newton_cooling(&samples).unwrap();
+
+        assert!((fit.ambient - AMBIENT).abs() < 1.0);
+        assert!((fit.cooling_constant - COOLING_CONSTANT).abs() < 0.0002);
+    }
+
+    #[test]
+    fn time_to_reach_matches_the_analytic_crossing() {
+        let samples = cooling_curve(&(0..300).step_by(5).collect::<Vec<i64>>());
+        let fit = fit_newton_cooling(&samples).unwrap();
+        let now = samples.last().unwrap().0;
+
+        let expected_crossing =
+            (INITIAL_DIFFERENCE / (30.0 - AMBIENT)).ln() / COOLING_CONSTANT - 295.0;
+        let time_left = fit.time_to_reach(30.0, now).unwrap().num_seconds() as f64;
+
+        assert!((time_left - expected_crossing).abs() < expected_crossing * 0.05);
+    }
+
+    #[test]
+    fn time_to_reach_below_ambient_is_none() {
+        let samples = cooling_curve(&(0..300).step_by(5).collect::<Vec<i64>>());
+        let fit = fit_newton_cooling(&samples).unwrap();
+
+        assert_eq!(fit.time_to_reach(15.0, samples[0].0), None);
+    }
+
+    #[test]
+    fn fit_newton_cooling_predicts_a_warming_curve() {
+        let bath = 45.0;
+        let samples: Vec<(DateTime<Utc>, f32)> = (0..300)
+            .step_by(5)
+            .map(|second| {
+                let temperature = bath - 40.0 * (-0.004 * second as f64).exp();
+                (
+                    DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(second),
+                    temperature as f32,
+                )
+            })
+            .collect();
+        let fit = fit_newton_cooling(&samples).unwrap();
+        let now = samples.last().unwrap().0;
+
+        let expected_crossing = (40.0f64 / (bath - 37.0)).ln() / 0.004 - 295.0;
+        let time_left = fit.time_to_reach(37.0, now).unwrap().num_seconds() as f64;
+
+        assert!((fit.ambient - bath).abs() < 1.0);
+        assert!((time_left - expected_crossing).abs() < expected_crossing * 0.05);
+        assert_eq!(fit.time_to_reach(50.0, now), None);
+    }
+
+    #[test]
+    fn fit_newton_cooling_needs_enough_samples() {
+        assert_eq!(fit_newton_cooling(&cooling_curve(&[0, 5, 10])), None);
+        assert_eq!(
+            fit_newton_cooling(&cooling_curve(&(0..20).collect::<Vec<i64>>())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_format_time_left() {
+        assert_eq!(
+            format_time_left(Duration::seconds(20)),
+            "less than a minute left"
+        );
+        assert_eq!(
+            format_time_left(Duration::seconds(70)),
+            "about 1 minute left"
+        );
+        assert_eq!(
+            format_time_left(Duration::seconds(725)),
+            "about 12 minutes left"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+
+/// Slope of a least-squares line through timestamped temperatures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoolingRate {
+    /// Degrees Celsius per second, negative while the water cools down.
+    pub rate_per_sec: f32,
+    /// Coefficient of determination of the fit, from 0 (noise) to 1 (perfect line).
+    pub r_squared: f32,
+    pub sample_count: usize,
+}
+
+/// Least-squares line `y = intercept + slope * x`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinearFit {
+    pub slope: f64,
+    pub intercept: f64,
+    pub r_squared: f64,
+}
+
+pub fn linear_regression(points: &[(f64, f64)]) -> Option<LinearFit> {
+    if points.len() < 2 {
+        return None;
+    }
+
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+
+    let mut x_variance = 0.0;
+    let mut covariance = 0.0;
+    let mut total_sum_of_squares = 0.0;
+    for (x, y) in points {
+        x_variance += (x - mean_x).powi(2);
+        covariance += (x - mean_x) * (y - mean_y);
+        total_sum_of_squares += (y - mean_y).powi(2);
+    }
+
+    if x_variance <= f64::EPSILON {
+        return None;
+    }
+
+    let slope = covariance / x_variance;
+    let intercept = mean_y - slope * mean_x;
+    let residual_sum_of_squares: f64 = points
+        .iter()
+        .map(|(x, y)| (y - (intercept + slope * x)).powi(2))
+        .sum();
+    let r_squared = if total_sum_of_squares <= f64::EPSILON {
+        1.0
+    } else {
+        (1.0 - residual_sum_of_squares / total_sum_of_squares).clamp(0.0, 1.0)
+    };
+
+    Some(LinearFit {
+        slope,
+        intercept,
+        r_squared,
+    })
+}
+
+/// Converts samples to `(seconds since the first sample, temperature)` points.
+pub fn elapsed_seconds(samples: &[(DateTime<Utc>, f32)]) -> Vec<(f64, f64)> {
+    let origin = match samples.first() {
+        Some((origin, _)) => *origin,
+        None => return Vec::new(),
+    };
+    samples
+        .iter()
+        .map(|(timestamp, temperature)| {
+            let elapsed = timestamp.signed_duration_since(origin);
+            (
+                elapsed.num_milliseconds() as f64 / 1000.0,
+                *temperature as f64,
+            )
+        })
+        .collect()
+}
+
+/// Fits `temperature = a + rate * t` over the samples.
+///
+/// Returns `None` when there are fewer than two samples or they all share the
+/// same timestamp, so the rate is always finite.
+pub fn estimate_cooling_rate(samples: &[(DateTime<Utc>, f32)]) -> Option<CoolingRate> {
+    let fit = linear_regression(&elapsed_seconds(samples))?;
+
+    Some(CoolingRate {
+        rate_per_sec: fit.slope as f32,
+        r_squared: fit.r_squared as f32,
+        sample_count: samples.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn samples(points: &[(i64, f32)]) -> Vec<(DateTime<Utc>, f32)> {
+        points
+            .iter()
+            .map(|(milliseconds, temperature)| {
+                (
+                    DateTime::<Utc>::UNIX_EPOCH + Duration::milliseconds(*milliseconds),
+                    *temperature,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_cooling_rate_on_a_perfect_line() {
+        let cooling_rate = estimate_cooling_rate(&samples(&[
+            (0, 80.0),
+            (1000, 79.5),
+            (2000, 79.0),
+            (3000, 78.5),
+        ]))
+        .unwrap();
+
+        assert!((cooling_rate.rate_per_sec + 0.5).abs() < 1e-6);
+        assert!((cooling_rate.r_squared - 1.0).abs() < 1e-6);
+        assert_eq!(cooling_rate.sample_count, 4);
+    }
+
+    #[test]
+    fn estimate_cooling_rate_uses_every_sample() {
+        // The first and last samples alone would give a rate of zero.
+        let cooling_rate = estimate_cooling_rate(&samples(&[
+            (0, 60.0),
+            (1000, 58.0),
+            (2000, 57.0),
+            (3000, 60.0),
+        ]))
+        .unwrap();
+
+        assert!(cooling_rate.rate_per_sec.abs() > 0.0);
+        assert!(cooling_rate.r_squared < 0.5);
+    }
+
+    #[test]
+    fn estimate_cooling_rate_handles_sub_second_spacing() {
+        let cooling_rate =
+            estimate_cooling_rate(&samples(&[(0, 50.0), (250, 49.75), (500, 49.5)])).unwrap();
+
+        assert!((cooling_rate.rate_per_sec + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn estimate_cooling_rate_without_time_spread_is_none() {
+        assert_eq!(estimate_cooling_rate(&samples(&[])), None);
+        assert_eq!(estimate_cooling_rate(&samples(&[(0, 50.0)])), None);
+        assert_eq!(
+            estimate_cooling_rate(&samples(&[(0, 50.0), (0, 49.0)])),
+            None
+        );
+    }
+}
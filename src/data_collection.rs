@@ -1,7 +1,9 @@
-use log::{error, info};
+use log::{debug, error, info, warn};
 
+use chrono::{DateTime, Utc};
 use core::fmt::Formatter;
 use reqwest::StatusCode;
+use serde::Serialize;
 use std::fmt::Display;
 
 cfg_if::cfg_if! {
@@ -11,7 +13,7 @@ cfg_if::cfg_if! {
         struct Client{
             url_passed_to_post: String,
             headers: Vec<(String, String)>,
-            body: HashMap<String, f32>,
+            body: serde_json::Value,
         }
 
         struct Response {
@@ -30,7 +32,7 @@ cfg_if::cfg_if! {
                 Client{
                     url_passed_to_post: "".to_string(),
                     headers: Vec::new(),
-                    body: HashMap::new(),
+                    body: serde_json::Value::Null,
                 }
             }
 
@@ -49,11 +51,11 @@ cfg_if::cfg_if! {
                 Client { url_passed_to_post: self.url_passed_to_post.clone(), headers: self.headers.clone(), body: self.body.clone() }
             }
 
-            pub fn json<'a>(&self, json_body: &HashMap<&'a str, f32>) -> Self {
+            pub fn json<T: Serialize + ?Sized>(&self, json_body: &T) -> Self {
                 Client {
                     url_passed_to_post: self.url_passed_to_post.clone(),
                     headers: self.headers.clone(),
-                    body: json_body.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+                    body: serde_json::to_value(json_body).unwrap(),
                 }
             }
 
@@ -73,15 +75,57 @@ cfg_if::cfg_if! {
     }
 }
 
-use std::{collections::HashMap, env};
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::devices::water_temperature_sensor::WaterTemperatureSensor;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::devices::water_temperature_sensor::{PreparationMode, WaterTemperatureSensor};
+use crate::notifications::DeliveryStatus;
+use crate::session::{BottleSession, SessionState, SessionTransition, TransitionReason};
 
 static DATA_COLLECTION_URL_KEY: &str = "DATA_COLLECTION_URL";
 static DATA_COLLECTION_ENABLED_KEY: &str = "DATA_COLLECTION_ENABLED";
 static DATA_COLLECTION_SECRET_KEY: &str = "DATA_COLLECTION_SECRET";
 
+const UPLOAD_QUEUE_CAPACITY: usize = 32;
+
+/// Everything uploaded about one reading, owned so it can be sent in the background.
+#[derive(Debug, Serialize)]
+pub struct DataCollectionPayload {
+    sensor_name: String,
+    sensor_serial: String,
+    profile: String,
+    mode: PreparationMode,
+    temperature_in_celcius: f32,
+    raw_temperature_in_celcius: f32,
+    time_to_target_in_seconds: Option<i64>,
+    too_cold: bool,
+    probe_removed: bool,
+    quarantined_readings: u32,
+    session_id: Option<String>,
+    session_state: SessionState,
+    session_state_entered_at: DateTime<Utc>,
+    session_state_reason: Option<TransitionReason>,
+    transitioned: bool,
+    sterilized: bool,
+    time_above_sterilization_in_seconds: i64,
+    notification_deliveries: Vec<NotificationDeliveryPayload>,
+}
+
+/// Last delivery status of one recipient of the bottle notifications.
+#[derive(Debug, Serialize)]
+struct NotificationDeliveryPayload {
+    channel: String,
+    recipient: String,
+    #[serde(flatten)]
+    status: DeliveryStatus,
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum DataCollectionError {
     DataCollectionDisabled,
     ValueHasNotChanged,
@@ -106,75 +150,199 @@ impl Display for DataCollectionError {
     }
 }
 
-pub async fn collect_data(
+/// Returns the latest reading when it changed or when the session just moved to a new
+/// state, along with the delivery status of each recipient of its notifications.
+pub fn prepare_data(
     water_temperature_sensor: &WaterTemperatureSensor,
-) -> Result<StatusCode, DataCollectionError> {
-    let collection_enabled: bool = env::var(DATA_COLLECTION_ENABLED_KEY.to_string())
+    bottle_session: &BottleSession,
+    transition: Option<&SessionTransition>,
+    delivery_statuses: HashMap<(String, String), DeliveryStatus>,
+) -> Result<DataCollectionPayload, DataCollectionError> {
+    let collection_enabled: bool = env::var(DATA_COLLECTION_ENABLED_KEY)
         .expect("DATA_COLLECTION_ENABLED must be set")
         .trim()
         .parse()
         .unwrap();
 
-    if collection_enabled == false {
+    if !collection_enabled {
         return Err(DataCollectionError::DataCollectionDisabled);
     }
 
-    if water_temperature_sensor.should_collect_data() {
-        let mut json_body = HashMap::new();
-        json_body.insert(
-            "temperature_in_celcius",
-            water_temperature_sensor.current_temperature,
-        );
+    if !water_temperature_sensor.should_collect_data() && transition.is_none() {
+        return Err(DataCollectionError::ValueHasNotChanged);
+    }
 
-        let url =
-            env::var(DATA_COLLECTION_URL_KEY.to_string()).expect("DATA_COLLECTION_URL must be set");
-
-        let data_collection_auth = env::var(DATA_COLLECTION_SECRET_KEY.to_string())
-            .expect("DATA_COLLECTION_SECRET must be set");
-
-        let result_query = Client::new()
-            .post(url)
-            .header("Content-Type", "application/json")
-            .header("X-Require-Whisk-Auth", data_collection_auth.as_str())
-            .json(&json_body)
-            .send()
-            .await;
-
-        match result_query {
-            Ok(response) => match response.status() {
-                StatusCode::OK | StatusCode::CREATED => {
-                    info!("Data collected successfully");
-                    return Ok(response.status());
-                }
-                _ => {
-                    error!("{}", response.status());
-                    return Err(DataCollectionError::DataCollectionError(response.status()));
-                }
+    let mut notification_deliveries: Vec<NotificationDeliveryPayload> = delivery_statuses
+        .into_iter()
+        .map(
+            |((channel, recipient), status)| NotificationDeliveryPayload {
+                channel,
+                recipient,
+                status,
             },
-            Err(e) => {
-                error!("Error: {}", e);
-                return Err(DataCollectionError::SystemError(e.to_string()));
+        )
+        .collect();
+    notification_deliveries.sort_by(|left, right| {
+        (&left.channel, &left.recipient).cmp(&(&right.channel, &right.recipient))
+    });
+
+    Ok(DataCollectionPayload {
+        sensor_name: water_temperature_sensor.name().to_string(),
+        sensor_serial: water_temperature_sensor.identity().to_string(),
+        profile: bottle_session.profile_name().to_string(),
+        mode: water_temperature_sensor.mode(),
+        temperature_in_celcius: water_temperature_sensor.current_temperature,
+        raw_temperature_in_celcius: water_temperature_sensor.raw_temperature(),
+        time_to_target_in_seconds: water_temperature_sensor
+            .get_time_to_target()
+            .map(|time_left| time_left.num_seconds()),
+        too_cold: water_temperature_sensor.is_too_cold(),
+        probe_removed: water_temperature_sensor.is_probe_removed(),
+        quarantined_readings: water_temperature_sensor.quarantined_count(),
+        session_id: bottle_session.id().map(str::to_string),
+        session_state: bottle_session.state(),
+        session_state_entered_at: bottle_session.entered_at(),
+        session_state_reason: bottle_session.reason(),
+        transitioned: transition.is_some(),
+        sterilized: bottle_session.is_sterilized(),
+        time_above_sterilization_in_seconds: bottle_session
+            .sterilization()
+            .time_above
+            .num_seconds(),
+        notification_deliveries,
+    })
+}
+
+pub async fn send_data(
+    json_body: &DataCollectionPayload,
+) -> Result<StatusCode, DataCollectionError> {
+    let url = env::var(DATA_COLLECTION_URL_KEY).expect("DATA_COLLECTION_URL must be set");
+
+    let data_collection_auth =
+        env::var(DATA_COLLECTION_SECRET_KEY).expect("DATA_COLLECTION_SECRET must be set");
+
+    let result_query = Client::new()
+        .post(url)
+        .header("Content-Type", "application/json")
+        .header("X-Require-Whisk-Auth", data_collection_auth.as_str())
+        .json(json_body)
+        .send()
+        .await;
+
+    match result_query {
+        Ok(response) => match response.status() {
+            StatusCode::OK | StatusCode::CREATED => {
+                info!("Data collected successfully");
+                Ok(response.status())
+            }
+            _ => {
+                error!("{}", response.status());
+                Err(DataCollectionError::DataCollectionError(response.status()))
             }
+        },
+        Err(e) => {
+            error!("Error: {}", e);
+            Err(DataCollectionError::SystemError(e.to_string()))
         }
-    } else {
-        return Err(DataCollectionError::ValueHasNotChanged);
+    }
+}
+
+/// Outcome of one upload, sent back so a reading only counts as reported once the
+/// server has it.
+pub struct UploadReport {
+    pub sensor_serial: String,
+    pub temperature: f32,
+    pub uploaded: bool,
+}
+
+/// Uploads from a background task so a slow server never holds up the monitoring.
+pub struct DataUploader {
+    payloads: UnboundedSender<DataCollectionPayload>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl DataUploader {
+    /// Starts the upload task, the receiver gets a report for every payload it sent.
+    pub fn spawn() -> (Self, UnboundedReceiver<UploadReport>) {
+        let (payloads, mut receiver) = mpsc::unbounded_channel::<DataCollectionPayload>();
+        let (reports, report_receiver) = mpsc::unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let worker_queued = queued.clone();
+        tokio::spawn(async move {
+            while let Some(payload) = receiver.recv().await {
+                worker_queued.fetch_sub(1, Ordering::SeqCst);
+                let uploaded = match send_data(&payload).await {
+                    Ok(status_code) => {
+                        debug!("Data collection status code: {}", status_code);
+                        true
+                    }
+                    Err(err) => {
+                        debug!("Data collection error: {}", err);
+                        false
+                    }
+                };
+                // Nobody is left to tell once the monitoring stopped.
+                let _ = reports.send(UploadReport {
+                    sensor_serial: payload.sensor_serial,
+                    temperature: payload.temperature_in_celcius,
+                    uploaded,
+                });
+            }
+        });
+        (DataUploader { payloads, queued }, report_receiver)
+    }
+
+    /// Queues the payload and returns false when it was dropped. Readings are dropped
+    /// once `UPLOAD_QUEUE_CAPACITY` of them wait for a slow server, session
+    /// transitions never are.
+    pub fn upload(&self, payload: DataCollectionPayload) -> bool {
+        if !payload.transitioned && self.queued.load(Ordering::SeqCst) >= UPLOAD_QUEUE_CAPACITY {
+            warn!(
+                "Data collection is falling behind, dropping a reading of {}",
+                payload.sensor_name
+            );
+            return false;
+        }
+        let sensor_name = payload.sensor_name.clone();
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if self.payloads.send(payload).is_err() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            error!(
+                "Data collection stopped, dropping the data of {}",
+                sensor_name
+            );
+            return false;
+        }
+        true
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::devices::water_temperature_sensor::WaterTemperatureSensor;
-
-    fn mock_env_variable(key_value_variables: HashMap<String, String>) {
+    use crate::devices::plausibility::PlausibilityPolicy;
+    use crate::devices::reading_filter::ReadingFilter;
+    use crate::devices::water_temperature_sensor::{TemperatureThreshold, WaterTemperatureSensor};
+    use crate::profile::Profile;
+    use std::collections::HashMap;
+    use tokio::sync::{Mutex, MutexGuard};
+
+    /// The settings are shared by the whole process, tests changing them take turns.
+    static ENVIRONMENT_LOCK: Mutex<()> = Mutex::const_new(());
+
+    async fn mock_env_variable(
+        key_value_variables: HashMap<String, String>,
+    ) -> MutexGuard<'static, ()> {
+        let environment = ENVIRONMENT_LOCK.lock().await;
         for key_value_variable in key_value_variables {
             env::remove_var(&key_value_variable.0);
             env::set_var(key_value_variable.0, key_value_variable.1);
         }
+        environment
     }
 
     #[tokio::test]
-    async fn collect_data_should_send_data_to_the_server() {
+    async fn send_data_should_send_data_to_the_server() {
         let key_value_variables = HashMap::from([
             (
                 DATA_COLLECTION_URL_KEY.to_string(),
@@ -184,12 +352,27 @@ mod tests {
             (DATA_COLLECTION_ENABLED_KEY.to_string(), "true".to_string()),
         ]);
 
-        mock_env_variable(key_value_variables);
+        let _environment = mock_env_variable(key_value_variables).await;
 
-        let mut water_temperature_sensor = WaterTemperatureSensor::new();
+        let mut water_temperature_sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            PlausibilityPolicy::default(),
+            ReadingFilter::None,
+        );
         water_temperature_sensor.current_temperature = 10.0;
 
-        let result = collect_data(&water_temperature_sensor).await;
+        let bottle_session = BottleSession::new("28-test".to_string(), &Profile::default());
+
+        let payload = prepare_data(
+            &water_temperature_sensor,
+            &bottle_session,
+            None,
+            HashMap::new(),
+        )
+        .unwrap();
+        let result = send_data(&payload).await;
 
         assert!(result.is_ok());
 
@@ -198,7 +381,7 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn collect_data_with_data_collection_disabled_should_return_error() {
+    async fn prepare_data_with_data_collection_disabled_should_return_error() {
         let key_value_variables = HashMap::from([
             (
                 DATA_COLLECTION_URL_KEY.to_string(),
@@ -208,13 +391,153 @@ mod tests {
             (DATA_COLLECTION_ENABLED_KEY.to_string(), "false".to_string()),
         ]);
 
-        mock_env_variable(key_value_variables);
+        let _environment = mock_env_variable(key_value_variables).await;
+
+        let mut water_temperature_sensor: WaterTemperatureSensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            PlausibilityPolicy::default(),
+            ReadingFilter::None,
+        );
+        water_temperature_sensor.current_temperature = 10.0;
+
+        let bottle_session = BottleSession::new("28-test".to_string(), &Profile::default());
+
+        let result = prepare_data(
+            &water_temperature_sensor,
+            &bottle_session,
+            None,
+            HashMap::new(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(DataCollectionError::DataCollectionDisabled)
+        ));
+    }
+
+    #[tokio::test]
+    async fn full_upload_queue_drops_readings_but_not_transitions() {
+        let key_value_variables = HashMap::from([
+            (
+                DATA_COLLECTION_URL_KEY.to_string(),
+                "http://127.0.0.1".to_string(),
+            ),
+            (DATA_COLLECTION_SECRET_KEY.to_string(), "Nope".to_string()),
+            (DATA_COLLECTION_ENABLED_KEY.to_string(), "true".to_string()),
+        ]);
+
+        let _environment = mock_env_variable(key_value_variables).await;
 
-        let mut water_temperature_sensor: WaterTemperatureSensor = WaterTemperatureSensor::new();
+        let mut water_temperature_sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            PlausibilityPolicy::default(),
+            ReadingFilter::None,
+        );
         water_temperature_sensor.current_temperature = 10.0;
 
-        let result = collect_data(&water_temperature_sensor).await;
+        let bottle_session = BottleSession::new("28-test".to_string(), &Profile::default());
+        let transition = SessionTransition {
+            session_id: "28-test-1".to_string(),
+            from: SessionState::Ready,
+            to: SessionState::Consumed,
+            reason: TransitionReason::MarkedConsumed,
+            at: Utc::now(),
+        };
+        let (uploader, mut upload_reports) = DataUploader::spawn();
+
+        for _ in 0..UPLOAD_QUEUE_CAPACITY {
+            let payload = prepare_data(
+                &water_temperature_sensor,
+                &bottle_session,
+                None,
+                HashMap::new(),
+            )
+            .unwrap();
+            assert!(uploader.upload(payload));
+        }
+        let payload = prepare_data(
+            &water_temperature_sensor,
+            &bottle_session,
+            None,
+            HashMap::new(),
+        )
+        .unwrap();
+        assert!(!uploader.upload(payload));
+        let payload = prepare_data(
+            &water_temperature_sensor,
+            &bottle_session,
+            Some(&transition),
+            HashMap::new(),
+        )
+        .unwrap();
+        assert!(uploader.upload(payload));
+
+        let upload_report = upload_reports.recv().await.unwrap();
+        assert_eq!(upload_report.sensor_serial, "28-test");
+        assert_eq!(upload_report.temperature, 10.0);
+        assert!(upload_report.uploaded);
+    }
 
-        assert!(result.is_err());
+    #[tokio::test]
+    async fn payload_lists_the_delivery_status_of_each_recipient() {
+        let key_value_variables = HashMap::from([
+            (
+                DATA_COLLECTION_URL_KEY.to_string(),
+                "http://127.0.0.1".to_string(),
+            ),
+            (DATA_COLLECTION_SECRET_KEY.to_string(), "Nope".to_string()),
+            (DATA_COLLECTION_ENABLED_KEY.to_string(), "true".to_string()),
+        ]);
+
+        let _environment = mock_env_variable(key_value_variables).await;
+
+        let mut water_temperature_sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            PlausibilityPolicy::default(),
+            ReadingFilter::None,
+        );
+        water_temperature_sensor.current_temperature = 10.0;
+
+        let bottle_session = BottleSession::new("28-test".to_string(), &Profile::default());
+        let delivery_statuses = HashMap::from([
+            (
+                ("sms".to_string(), "+15550002".to_string()),
+                DeliveryStatus::Failed {
+                    reason: "Twilio is down".to_string(),
+                    attempts: 3,
+                    at: Utc::now(),
+                },
+            ),
+            (
+                ("sms".to_string(), "+15550001".to_string()),
+                DeliveryStatus::Sent {
+                    message_id: Some("SM1".to_string()),
+                    at: Utc::now(),
+                },
+            ),
+        ]);
+
+        let payload = prepare_data(
+            &water_temperature_sensor,
+            &bottle_session,
+            None,
+            delivery_statuses,
+        )
+        .unwrap();
+        let payload = serde_json::to_value(&payload).unwrap();
+
+        let deliveries = &payload["notification_deliveries"];
+        assert_eq!(deliveries[0]["recipient"], "+15550001");
+        assert_eq!(deliveries[0]["status"], "sent");
+        assert_eq!(deliveries[0]["message_id"], "SM1");
+        assert_eq!(deliveries[1]["recipient"], "+15550002");
+        assert_eq!(deliveries[1]["status"], "failed");
+        assert_eq!(deliveries[1]["attempts"], 3);
     }
 }
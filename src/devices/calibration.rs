@@ -0,0 +1,137 @@
+use core::fmt::Formatter;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+
+use crate::helpers::{get_env_or_default, parse_key_value_list};
+
+pub static SENSOR_CALIBRATIONS_KEY: &str = "SENSOR_CALIBRATIONS";
+static CALIBRATION_BOILING_POINT_KEY: &str = "CALIBRATION_BOILING_POINT";
+
+pub const ICE_POINT: f32 = 0.0;
+const DEFAULT_CALIBRATION_BOILING_POINT: f32 = 100.0;
+
+/// Correction applied to the raw readings of one probe.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Calibration {
+    None,
+    /// Added to every reading.
+    Offset(f32),
+    /// What the probe read in ice water and in boiling water, mapped linearly onto
+    /// `ICE_POINT` and `boiling_point`.
+    TwoPoint {
+        ice_reading: f32,
+        boiling_reading: f32,
+        boiling_point: f32,
+    },
+}
+
+impl Calibration {
+    /// Parses `<offset>` or `<ice reading>:<boiling reading>`.
+    pub fn parse(raw_calibration: &str, boiling_point: f32) -> Result<Self, String> {
+        let invalid = || format!("Invalid calibration: {}", raw_calibration);
+        match raw_calibration.split_once(':') {
+            Some((ice_reading, boiling_reading)) => {
+                let ice_reading: f32 = ice_reading.trim().parse().map_err(|_| invalid())?;
+                let boiling_reading: f32 = boiling_reading.trim().parse().map_err(|_| invalid())?;
+                if boiling_reading <= ice_reading {
+                    return Err(invalid());
+                }
+                Ok(Calibration::TwoPoint {
+                    ice_reading,
+                    boiling_reading,
+                    boiling_point,
+                })
+            }
+            None => Ok(Calibration::Offset(
+                raw_calibration.trim().parse().map_err(|_| invalid())?,
+            )),
+        }
+    }
+
+    pub fn apply(&self, raw_temperature: f32) -> f32 {
+        match self {
+            Calibration::None => raw_temperature,
+            Calibration::Offset(offset) => raw_temperature + offset,
+            Calibration::TwoPoint {
+                ice_reading,
+                boiling_reading,
+                boiling_point,
+            } => {
+                ICE_POINT
+                    + (raw_temperature - ice_reading) * (boiling_point - ICE_POINT)
+                        / (boiling_reading - ice_reading)
+            }
+        }
+    }
+}
+
+/// Formats the calibration the way `Calibration::parse` reads it back.
+impl Display for Calibration {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Calibration::None => write!(formatter, "0"),
+            Calibration::Offset(offset) => write!(formatter, "{}", offset),
+            Calibration::TwoPoint {
+                ice_reading,
+                boiling_reading,
+                ..
+            } => write!(formatter, "{}:{}", ice_reading, boiling_reading),
+        }
+    }
+}
+
+pub fn get_calibration_boiling_point() -> f32 {
+    get_env_or_default(
+        CALIBRATION_BOILING_POINT_KEY,
+        DEFAULT_CALIBRATION_BOILING_POINT,
+    )
+}
+
+/// Calibrations per probe serial, from `SENSOR_CALIBRATIONS`.
+pub fn load_calibrations() -> HashMap<String, Calibration> {
+    let boiling_point = get_calibration_boiling_point();
+    parse_key_value_list(&env::var(SENSOR_CALIBRATIONS_KEY).unwrap_or_default())
+        .into_iter()
+        .map(|(serial, raw_calibration)| {
+            let calibration = Calibration::parse(&raw_calibration, boiling_point)
+                .unwrap_or_else(|err| panic!("{} for {}", err, serial));
+            (serial, calibration)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_is_added_to_the_reading() {
+        let calibration = Calibration::parse("-0.4", 100.0).unwrap();
+        assert_eq!(calibration, Calibration::Offset(-0.4));
+        assert!((calibration.apply(30.4) - 30.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn two_point_maps_both_references() {
+        let calibration = Calibration::parse("0.5:99.5", 100.0).unwrap();
+
+        assert!((calibration.apply(0.5) - 0.0).abs() < 1e-4);
+        assert!((calibration.apply(99.5) - 100.0).abs() < 1e-4);
+        assert!((calibration.apply(30.2) - 30.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn calibration_round_trips_through_display() {
+        for raw_calibration in ["0.25", "-0.5:98.75"] {
+            let calibration = Calibration::parse(raw_calibration, 100.0).unwrap();
+            assert_eq!(calibration.to_string(), raw_calibration);
+        }
+    }
+
+    #[test]
+    fn invalid_calibrations_are_rejected() {
+        assert!(Calibration::parse("warm", 100.0).is_err());
+        assert!(Calibration::parse("99.0:0.5", 100.0).is_err());
+    }
+}
@@ -0,0 +1,478 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::devices::calibration::Calibration;
+use crate::devices::sensor_error::SensorError;
+use crate::devices::temperature_source::{TemperatureReading, TemperatureSource};
+
+pub const BASE_DIR_TEMPERATURE_SENSOR: &str = "/sys/bus/w1/devices/";
+const DS18B20_FAMILY_PREFIX: &str = "28-";
+
+const POWER_ON_RESET_MILLIDEGREES: i32 = 85000;
+const DISCONNECTED_MILLIDEGREES: [i32; 2] = [-127000, -1250];
+
+const MINIMUM_RESOLUTION_BITS: u8 = 9;
+const MAXIMUM_RESOLUTION_BITS: u8 = 12;
+const MAXIMUM_RESOLUTION_CONVERSION_TIME: Duration = Duration::from_millis(750);
+const MAXIMUM_RESOLUTION_STEP: f32 = 0.0625;
+
+const INITIAL_RESCAN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESCAN_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    /// `w1_slave`: two lines of scratchpad bytes with the CRC result and `t=<millidegrees>`.
+    W1Slave,
+    /// `temperature`: only the millidegrees, exposed by newer kernels.
+    Temperature,
+}
+
+/// Measurement resolution of a probe, from 9 bits (0.5C) to 12 bits (0.0625C).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resolution(u8);
+
+impl Resolution {
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Time the probe needs to convert a reading, halved for every bit below 12.
+    pub fn conversion_time(&self) -> Duration {
+        MAXIMUM_RESOLUTION_CONVERSION_TIME / (1 << (MAXIMUM_RESOLUTION_BITS - self.0))
+    }
+
+    /// Smallest temperature change the probe reports, doubled for every bit below 12.
+    pub fn step(&self) -> f32 {
+        MAXIMUM_RESOLUTION_STEP * (1 << (MAXIMUM_RESOLUTION_BITS - self.0)) as f32
+    }
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution(MAXIMUM_RESOLUTION_BITS)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(bits: &str) -> Result<Self, Self::Err> {
+        match bits.trim().parse::<u8>() {
+            Ok(bits) if (MINIMUM_RESOLUTION_BITS..=MAXIMUM_RESOLUTION_BITS).contains(&bits) => {
+                Ok(Resolution(bits))
+            }
+            _ => Err(format!(
+                "Resolution must be {} to {} bits, got {}",
+                MINIMUM_RESOLUTION_BITS, MAXIMUM_RESOLUTION_BITS, bits
+            )),
+        }
+    }
+}
+
+pub struct Ds18b20 {
+    serial: String,
+    base_directory: PathBuf,
+    device_directory: PathBuf,
+    temperature_filepath: PathBuf,
+    output_format: OutputFormat,
+    fault_count: u32,
+    attached: bool,
+    rescan_backoff: Duration,
+    next_rescan_at: Instant,
+    calibration: Calibration,
+    resolution: Resolution,
+}
+
+impl Ds18b20 {
+    pub fn new(base_directory: &Path, serial: String) -> Self {
+        let device_directory = base_directory.join(&serial);
+        let (temperature_filepath, output_format) =
+            Ds18b20::resolve_temperature_filepath(&device_directory);
+        let resolution = Ds18b20::read_resolution(&device_directory).unwrap_or_default();
+        info!(
+            "Found temperature sensor at {} with a {} bits resolution",
+            temperature_filepath.display(),
+            resolution.bits()
+        );
+
+        Ds18b20 {
+            serial,
+            base_directory: base_directory.to_path_buf(),
+            device_directory,
+            temperature_filepath,
+            output_format,
+            fault_count: 0,
+            attached: true,
+            rescan_backoff: INITIAL_RESCAN_BACKOFF,
+            next_rescan_at: Instant::now(),
+            calibration: Calibration::None,
+            resolution,
+        }
+    }
+
+    /// Corrects every following reading of this probe.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        info!("Calibration of {}: {:?}", self.serial, calibration);
+        self.calibration = calibration;
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Writes `resolution` to the probe, unless it already uses it.
+    pub fn set_resolution(&mut self, resolution: Resolution) -> Result<(), SensorError> {
+        let current_resolution =
+            Ds18b20::read_resolution(&self.device_directory).unwrap_or(self.resolution);
+        if current_resolution != resolution {
+            fs::write(
+                self.device_directory.join("resolution"),
+                resolution.bits().to_string(),
+            )
+            .map_err(|err| SensorError::Io(err.to_string()))?;
+            info!(
+                "Resolution of {} set to {} bits",
+                self.serial,
+                resolution.bits()
+            );
+        }
+        self.resolution = resolution;
+        Ok(())
+    }
+
+    /// Reads the `resolution` attribute, missing on kernels older than 5.10.
+    fn read_resolution(device_directory: &Path) -> Option<Resolution> {
+        fs::read_to_string(device_directory.join("resolution"))
+            .ok()
+            .and_then(|bits| bits.parse().ok())
+    }
+
+    fn resolve_temperature_filepath(device_directory: &Path) -> (PathBuf, OutputFormat) {
+        let w1_slave_filepath = device_directory.join("w1_slave");
+        if w1_slave_filepath.exists() {
+            (w1_slave_filepath, OutputFormat::W1Slave)
+        } else {
+            (
+                device_directory.join("temperature"),
+                OutputFormat::Temperature,
+            )
+        }
+    }
+
+    /// Returns one source per DS18B20 found under `base_directory`, ordered by serial.
+    pub fn discover(base_directory: &Path) -> Result<Vec<Self>, SensorError> {
+        let serials = Ds18b20::find_serials(base_directory)?;
+        if serials.is_empty() {
+            return Err(SensorError::NotFound);
+        }
+        Ok(serials
+            .into_iter()
+            .map(|serial| Ds18b20::new(base_directory, serial))
+            .collect())
+    }
+
+    fn find_serials(base_directory: &Path) -> Result<Vec<String>, SensorError> {
+        let directories =
+            fs::read_dir(base_directory).map_err(|err| SensorError::Io(err.to_string()))?;
+
+        let mut serials: Vec<String> = directories
+            .flatten()
+            .filter_map(|directory| directory.file_name().into_string().ok())
+            .filter(|directory_name| directory_name.starts_with(DS18B20_FAMILY_PREFIX))
+            .collect();
+        serials.sort();
+        Ok(serials)
+    }
+
+    fn detach(&mut self) {
+        warn!(
+            "Temperature sensor {} disappeared from the bus",
+            self.serial
+        );
+        self.attached = false;
+        self.rescan_backoff = INITIAL_RESCAN_BACKOFF;
+        self.next_rescan_at = Instant::now() + self.rescan_backoff;
+    }
+
+    /// Rescans the bus for our serial, doubling the wait between scans while it is missing.
+    fn try_reattach(&mut self) -> Result<(), SensorError> {
+        if Instant::now() < self.next_rescan_at {
+            return Err(SensorError::NotFound);
+        }
+
+        let serials = Ds18b20::find_serials(&self.base_directory)?;
+        if !serials.contains(&self.serial) {
+            self.rescan_backoff = (self.rescan_backoff * 2).min(MAX_RESCAN_BACKOFF);
+            self.next_rescan_at = Instant::now() + self.rescan_backoff;
+            debug!(
+                "Temperature sensor {} still missing, next scan in {:?}",
+                self.serial, self.rescan_backoff
+            );
+            return Err(SensorError::NotFound);
+        }
+
+        let (temperature_filepath, output_format) =
+            Ds18b20::resolve_temperature_filepath(&self.device_directory);
+        info!(
+            "Temperature sensor {} is back at {}",
+            self.serial,
+            temperature_filepath.display()
+        );
+        self.temperature_filepath = temperature_filepath;
+        self.output_format = output_format;
+        self.attached = true;
+        // A power cycle restores the resolution saved in the probe EEPROM.
+        if let Err(err) = self.set_resolution(self.resolution) {
+            warn!(
+                "Unable to restore the resolution of {}: {}",
+                self.serial, err
+            );
+        }
+        Ok(())
+    }
+
+    fn read_temperature(&self) -> Result<f32, SensorError> {
+        let content = fs::read_to_string(&self.temperature_filepath)
+            .map_err(|err| SensorError::Io(err.to_string()))?;
+        let millidegrees = match self.output_format {
+            OutputFormat::W1Slave => parse_w1_slave(&content)?,
+            OutputFormat::Temperature => parse_millidegrees(&content)?,
+        };
+        validate_millidegrees(millidegrees).map(|temperature| self.calibration.apply(temperature))
+    }
+}
+
+impl TemperatureSource for Ds18b20 {
+    fn read(&mut self) -> Result<TemperatureReading, SensorError> {
+        if !self.attached {
+            self.try_reattach()?;
+        }
+
+        debug!(
+            "Reading temperature from {}",
+            self.temperature_filepath.display()
+        );
+        match self.read_temperature() {
+            Ok(temperature) => Ok(TemperatureReading::new(temperature)),
+            Err(err) => {
+                self.fault_count += 1;
+                if !self.device_directory.exists() {
+                    self.detach();
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn identity(&self) -> String {
+        self.serial.clone()
+    }
+
+    fn fault_count(&self) -> u32 {
+        self.fault_count
+    }
+
+    fn is_attached(&self) -> bool {
+        self.attached
+    }
+}
+
+fn parse_millidegrees(content: &str) -> Result<i32, SensorError> {
+    content
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| SensorError::Malformed(content.to_string()))
+}
+
+fn parse_w1_slave(content: &str) -> Result<i32, SensorError> {
+    let mut lines = content.lines();
+    let crc_line = lines
+        .next()
+        .ok_or_else(|| SensorError::Malformed(content.to_string()))?;
+    let temperature_line = lines
+        .next()
+        .ok_or_else(|| SensorError::Malformed(content.to_string()))?;
+
+    if !crc_line.contains("crc=") {
+        return Err(SensorError::Malformed(content.to_string()));
+    }
+    if !crc_line.trim_end().ends_with("YES") {
+        return Err(SensorError::CrcMismatch);
+    }
+
+    match temperature_line.split_once("t=") {
+        Some((_, millidegrees)) => parse_millidegrees(millidegrees),
+        None => Err(SensorError::Malformed(content.to_string())),
+    }
+}
+
+fn validate_millidegrees(millidegrees: i32) -> Result<f32, SensorError> {
+    if millidegrees == POWER_ON_RESET_MILLIDEGREES {
+        return Err(SensorError::PowerOnReset);
+    }
+    if DISCONNECTED_MILLIDEGREES.contains(&millidegrees) {
+        return Err(SensorError::Disconnected(millidegrees));
+    }
+    Ok(millidegrees as f32 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::fake_w1_bus::FakeW1Bus;
+
+    #[test]
+    fn parse_w1_slave_with_valid_crc() {
+        let content =
+            "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content), Ok(23125));
+    }
+
+    #[test]
+    fn parse_w1_slave_with_invalid_crc() {
+        let content =
+            "72 01 4b 46 7f ff 0e 10 57 : crc=ff NO\n72 01 4b 46 7f ff 0e 10 57 t=23125\n";
+        assert_eq!(parse_w1_slave(content), Err(SensorError::CrcMismatch));
+    }
+
+    #[test]
+    fn parse_w1_slave_truncated() {
+        let content = "72 01 4b 46 7f ff 0e 10 57 : crc=57 YES\n";
+        assert!(matches!(
+            parse_w1_slave(content),
+            Err(SensorError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn validate_millidegrees_rejects_bogus_values() {
+        assert_eq!(validate_millidegrees(85000), Err(SensorError::PowerOnReset));
+        assert_eq!(
+            validate_millidegrees(-127000),
+            Err(SensorError::Disconnected(-127000))
+        );
+        assert_eq!(
+            validate_millidegrees(-1250),
+            Err(SensorError::Disconnected(-1250))
+        );
+        assert_eq!(validate_millidegrees(31500), Ok(31.5));
+    }
+
+    #[test]
+    fn discover_finds_every_ds18b20_in_order() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000bb", 21000, true);
+        bus.add_temperature("28-0000000000aa", "22000\n");
+        bus.add_other_device("w1_bus_master1");
+
+        let serials: Vec<String> = Ds18b20::discover(bus.path())
+            .unwrap()
+            .iter()
+            .map(|sensor| sensor.identity())
+            .collect();
+
+        assert_eq!(serials, vec!["28-0000000000aa", "28-0000000000bb"]);
+    }
+
+    #[test]
+    fn discover_without_probe_is_not_found() {
+        let bus = FakeW1Bus::new();
+        bus.add_other_device("w1_bus_master1");
+
+        assert_eq!(
+            Ds18b20::discover(bus.path()).err(),
+            Some(SensorError::NotFound)
+        );
+    }
+
+    #[test]
+    fn read_from_both_output_formats() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000aa", 45250, true);
+        bus.add_temperature("28-0000000000bb", "29875\n");
+
+        let mut w1_slave = Ds18b20::new(bus.path(), "28-0000000000aa".to_string());
+        let mut temperature = Ds18b20::new(bus.path(), "28-0000000000bb".to_string());
+
+        assert_eq!(w1_slave.read().unwrap().temperature, 45.25);
+        assert_eq!(temperature.read().unwrap().temperature, 29.875);
+    }
+
+    #[test]
+    fn read_applies_the_calibration() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000aa", 30500, true);
+        let mut sensor = Ds18b20::new(bus.path(), "28-0000000000aa".to_string());
+
+        sensor.set_calibration(Calibration::Offset(-0.5));
+        assert_eq!(sensor.read().unwrap().temperature, 30.0);
+    }
+
+    #[test]
+    fn resolution_sets_the_conversion_time() {
+        assert_eq!("9".parse(), Ok(Resolution(9)));
+        assert!("8".parse::<Resolution>().is_err());
+        assert!("13".parse::<Resolution>().is_err());
+
+        assert_eq!(
+            Resolution(9).conversion_time(),
+            Duration::from_micros(93750)
+        );
+        assert_eq!(
+            Resolution::default().conversion_time(),
+            Duration::from_millis(750)
+        );
+        assert_eq!(Resolution(9).step(), 0.5);
+        assert_eq!(Resolution::default().step(), 0.0625);
+    }
+
+    #[test]
+    fn set_resolution_writes_the_attribute() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000aa", 30500, true);
+        bus.set_resolution("28-0000000000aa", 12);
+        let mut sensor = Ds18b20::new(bus.path(), "28-0000000000aa".to_string());
+        assert_eq!(sensor.resolution(), Resolution(12));
+
+        sensor.set_resolution(Resolution(10)).unwrap();
+
+        assert_eq!(bus.resolution("28-0000000000aa"), "10");
+        assert_eq!(sensor.resolution(), Resolution(10));
+    }
+
+    #[test]
+    fn read_failures_are_counted() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000aa", 45250, false);
+        let mut sensor = Ds18b20::new(bus.path(), "28-0000000000aa".to_string());
+
+        assert_eq!(sensor.read(), Err(SensorError::CrcMismatch));
+
+        bus.add_w1_slave("28-0000000000aa", 85000, true);
+        assert_eq!(sensor.read(), Err(SensorError::PowerOnReset));
+        assert_eq!(sensor.fault_count(), 2);
+        assert!(sensor.is_attached());
+    }
+
+    #[test]
+    fn read_detaches_and_reattaches_to_the_same_serial() {
+        let bus = FakeW1Bus::new();
+        bus.add_w1_slave("28-0000000000aa", 45250, true);
+        let mut sensor = Ds18b20::new(bus.path(), "28-0000000000aa".to_string());
+
+        bus.remove("28-0000000000aa");
+        assert!(matches!(sensor.read(), Err(SensorError::Io(_))));
+        assert!(!sensor.is_attached());
+
+        bus.add_temperature("28-0000000000aa", "30500\n");
+        assert_eq!(sensor.read(), Err(SensorError::NotFound));
+
+        sensor.next_rescan_at = Instant::now();
+        assert_eq!(sensor.read().unwrap().temperature, 30.5);
+        assert!(sensor.is_attached());
+    }
+}
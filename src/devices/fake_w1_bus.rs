@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+use tempfile::TempDir;
+
+/// A throwaway `/sys/bus/w1/devices/` look-alike for exercising the 1-Wire backend.
+pub struct FakeW1Bus {
+    root: TempDir,
+}
+
+impl FakeW1Bus {
+    pub fn new() -> Self {
+        FakeW1Bus {
+            root: TempDir::new().expect("Unable to create fake w1 bus"),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.root.path()
+    }
+
+    /// Adds a probe exposing the `w1_slave` file with the given CRC result.
+    pub fn add_w1_slave(&self, serial: &str, millidegrees: i32, crc_ok: bool) {
+        let crc_status = if crc_ok { "YES" } else { "NO" };
+        self.write(
+            serial,
+            "w1_slave",
+            &format!(
+                "72 01 4b 46 7f ff 0e 10 57 : crc=57 {}\n72 01 4b 46 7f ff 0e 10 57 t={}\n",
+                crc_status, millidegrees
+            ),
+        );
+    }
+
+    /// Adds a probe exposing only the `temperature` file of newer kernels.
+    pub fn add_temperature(&self, serial: &str, content: &str) {
+        self.write(serial, "temperature", content);
+    }
+
+    /// Adds a non DS18B20 device, such as the bus master.
+    pub fn add_other_device(&self, name: &str) {
+        fs::create_dir_all(self.root.path().join(name)).unwrap();
+    }
+
+    /// Sets the `resolution` attribute of an existing probe.
+    pub fn set_resolution(&self, serial: &str, bits: u8) {
+        self.write(serial, "resolution", &format!("{}\n", bits));
+    }
+
+    pub fn resolution(&self, serial: &str) -> String {
+        fs::read_to_string(self.root.path().join(serial).join("resolution"))
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    pub fn remove(&self, serial: &str) {
+        fs::remove_dir_all(self.root.path().join(serial)).unwrap();
+    }
+
+    fn write(&self, serial: &str, file_name: &str, content: &str) {
+        let device_directory = self.root.path().join(serial);
+        fs::create_dir_all(&device_directory).unwrap();
+        fs::write(device_directory.join(file_name), content).unwrap();
+    }
+}
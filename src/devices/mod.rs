@@ -1 +1,10 @@
+pub mod calibration;
+pub mod ds18b20;
+#[cfg(test)]
+pub mod fake_w1_bus;
+pub mod plausibility;
+pub mod poller;
+pub mod reading_filter;
+pub mod sensor_error;
+pub mod temperature_source;
 pub mod water_temperature_sensor;
@@ -0,0 +1,308 @@
+use core::fmt::Formatter;
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::devices::temperature_source::TemperatureReading;
+use crate::helpers::get_env_or_default;
+
+static MAXIMUM_SLEW_RATE_PER_SECOND_KEY: &str = "MAXIMUM_SLEW_RATE_PER_SECOND";
+static STALE_READING_TIMEOUT_IN_SECONDS_KEY: &str = "STALE_READING_TIMEOUT_IN_SECONDS";
+static MINIMUM_VALID_TEMPERATURE_KEY: &str = "MINIMUM_VALID_TEMPERATURE";
+static MAXIMUM_VALID_TEMPERATURE_KEY: &str = "MAXIMUM_VALID_TEMPERATURE";
+static SENSOR_UNRELIABLE_AFTER_READINGS_KEY: &str = "SENSOR_UNRELIABLE_AFTER_READINGS";
+
+const DEFAULT_MAXIMUM_SLEW_RATE_PER_SECOND: f32 = 25.0;
+const DEFAULT_STALE_READING_TIMEOUT_IN_SECONDS: i64 = 1800;
+const DEFAULT_MINIMUM_VALID_TEMPERATURE: f32 = -10.0;
+const DEFAULT_MAXIMUM_VALID_TEMPERATURE: f32 = 110.0;
+const DEFAULT_SENSOR_UNRELIABLE_AFTER_READINGS: u32 = 10;
+/// Step of a 12-bit probe, the default `stale_after` is meant for. A coarser probe
+/// can read the same value for longer while the water slowly changes.
+const DEFAULT_READING_STEP: f32 = 0.0625;
+
+/// Limits a reading must respect to be trusted. A zero `stale_after` disables the
+/// stuck value check, which only applies while the water should be changing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlausibilityPolicy {
+    pub maximum_slew_rate_per_second: f32,
+    pub stale_after: Duration,
+    pub minimum_temperature: f32,
+    pub maximum_temperature: f32,
+    pub unreliable_after: u32,
+}
+
+impl PlausibilityPolicy {
+    pub fn from_env() -> Self {
+        PlausibilityPolicy {
+            maximum_slew_rate_per_second: get_env_or_default(
+                MAXIMUM_SLEW_RATE_PER_SECOND_KEY,
+                DEFAULT_MAXIMUM_SLEW_RATE_PER_SECOND,
+            ),
+            stale_after: Duration::seconds(get_env_or_default(
+                STALE_READING_TIMEOUT_IN_SECONDS_KEY,
+                DEFAULT_STALE_READING_TIMEOUT_IN_SECONDS,
+            )),
+            minimum_temperature: get_env_or_default(
+                MINIMUM_VALID_TEMPERATURE_KEY,
+                DEFAULT_MINIMUM_VALID_TEMPERATURE,
+            ),
+            maximum_temperature: get_env_or_default(
+                MAXIMUM_VALID_TEMPERATURE_KEY,
+                DEFAULT_MAXIMUM_VALID_TEMPERATURE,
+            ),
+            unreliable_after: get_env_or_default(
+                SENSOR_UNRELIABLE_AFTER_READINGS_KEY,
+                DEFAULT_SENSOR_UNRELIABLE_AFTER_READINGS,
+            ),
+        }
+    }
+}
+
+impl Default for PlausibilityPolicy {
+    fn default() -> Self {
+        PlausibilityPolicy {
+            maximum_slew_rate_per_second: DEFAULT_MAXIMUM_SLEW_RATE_PER_SECOND,
+            stale_after: Duration::seconds(DEFAULT_STALE_READING_TIMEOUT_IN_SECONDS),
+            minimum_temperature: DEFAULT_MINIMUM_VALID_TEMPERATURE,
+            maximum_temperature: DEFAULT_MAXIMUM_VALID_TEMPERATURE,
+            unreliable_after: DEFAULT_SENSOR_UNRELIABLE_AFTER_READINGS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImplausibleReading {
+    OutOfRange(f32),
+    SlewRate(f32),
+    Stale(Duration),
+}
+
+impl Display for ImplausibleReading {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImplausibleReading::OutOfRange(temperature) => {
+                write!(formatter, "{}C is outside the valid range", temperature)
+            }
+            ImplausibleReading::SlewRate(rate_per_second) => {
+                write!(
+                    formatter,
+                    "changed by {:.1}C/s, faster than water can",
+                    rate_per_second
+                )
+            }
+            ImplausibleReading::Stale(unchanged_for) => {
+                write!(
+                    formatter,
+                    "value unchanged for {}s",
+                    unchanged_for.num_seconds()
+                )
+            }
+        }
+    }
+}
+
+/// Quarantines readings that a working probe in water cannot produce.
+pub struct PlausibilityCheck {
+    policy: PlausibilityPolicy,
+    last_accepted: Option<TemperatureReading>,
+    unchanged_since: Option<DateTime<Utc>>,
+    expecting_change: bool,
+    reading_step: f32,
+    consecutive_failures: u32,
+    quarantined_count: u32,
+}
+
+impl PlausibilityCheck {
+    pub fn new(policy: PlausibilityPolicy) -> Self {
+        PlausibilityCheck {
+            policy,
+            last_accepted: None,
+            unchanged_since: None,
+            expecting_change: false,
+            reading_step: DEFAULT_READING_STEP,
+            consecutive_failures: 0,
+            quarantined_count: 0,
+        }
+    }
+
+    /// Total readings rejected since start.
+    pub fn quarantined_count(&self) -> u32 {
+        self.quarantined_count
+    }
+
+    /// True once enough readings in a row were rejected.
+    pub fn is_unreliable(&self) -> bool {
+        self.policy.unreliable_after > 0
+            && self.consecutive_failures >= self.policy.unreliable_after
+    }
+
+    /// Enables the stuck value check while a bottle is in progress, an idle probe
+    /// on the counter may read the same value for hours.
+    pub fn set_expecting_change(&mut self, expecting_change: bool) {
+        if expecting_change && !self.expecting_change {
+            self.unchanged_since = None;
+        }
+        self.expecting_change = expecting_change;
+    }
+
+    /// Smallest change the probe reports, `stale_after` grows with it.
+    pub fn set_reading_step(&mut self, reading_step: f32) {
+        self.reading_step = reading_step;
+    }
+
+    pub fn check(&mut self, reading: &TemperatureReading) -> Result<(), ImplausibleReading> {
+        match self.evaluate(reading) {
+            Ok(()) => {
+                if self.unchanged_since.is_none()
+                    || self.last_accepted.is_none_or(|last_accepted| {
+                        last_accepted.temperature != reading.temperature
+                    })
+                {
+                    self.unchanged_since = Some(reading.timestamp);
+                }
+                self.last_accepted = Some(*reading);
+                self.consecutive_failures = 0;
+                Ok(())
+            }
+            Err(implausible) => {
+                self.consecutive_failures += 1;
+                self.quarantined_count += 1;
+                Err(implausible)
+            }
+        }
+    }
+
+    fn evaluate(&self, reading: &TemperatureReading) -> Result<(), ImplausibleReading> {
+        if reading.temperature < self.policy.minimum_temperature
+            || reading.temperature > self.policy.maximum_temperature
+        {
+            return Err(ImplausibleReading::OutOfRange(reading.temperature));
+        }
+
+        let last_accepted = match self.last_accepted {
+            Some(last_accepted) => last_accepted,
+            None => return Ok(()),
+        };
+        let elapsed =
+            (reading.timestamp - last_accepted.timestamp).num_milliseconds() as f32 / 1000.0;
+        let change = (reading.temperature - last_accepted.temperature).abs();
+        if elapsed > 0.0 && change / elapsed > self.policy.maximum_slew_rate_per_second {
+            return Err(ImplausibleReading::SlewRate(change / elapsed));
+        }
+
+        if let Some(unchanged_since) = self.unchanged_since {
+            let unchanged_for = reading.timestamp - unchanged_since;
+            if change == 0.0
+                && self.expecting_change
+                && self.policy.stale_after > Duration::zero()
+                && unchanged_for > self.stale_after()
+            {
+                return Err(ImplausibleReading::Stale(unchanged_for));
+            }
+        }
+        Ok(())
+    }
+
+    fn stale_after(&self) -> Duration {
+        let steps = (self.reading_step / DEFAULT_READING_STEP).max(1.0);
+        Duration::milliseconds((self.policy.stale_after.num_milliseconds() as f32 * steps) as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(seconds: i64, temperature: f32) -> TemperatureReading {
+        TemperatureReading {
+            temperature,
+            timestamp: DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(seconds),
+        }
+    }
+
+    fn check() -> PlausibilityCheck {
+        let mut check = PlausibilityCheck::new(PlausibilityPolicy {
+            stale_after: Duration::seconds(60),
+            unreliable_after: 3,
+            ..PlausibilityPolicy::default()
+        });
+        check.set_expecting_change(true);
+        check
+    }
+
+    #[test]
+    fn out_of_range_readings_are_quarantined() {
+        let mut check = check();
+
+        assert_eq!(
+            check.check(&reading(0, 127.0)),
+            Err(ImplausibleReading::OutOfRange(127.0))
+        );
+        assert_eq!(check.check(&reading(1, 22.0)), Ok(()));
+        assert_eq!(check.quarantined_count(), 1);
+    }
+
+    #[test]
+    fn jumps_faster_than_the_slew_rate_are_quarantined() {
+        let mut check = check();
+        check.check(&reading(0, 22.0)).unwrap();
+
+        assert_eq!(
+            check.check(&reading(1, 62.0)),
+            Err(ImplausibleReading::SlewRate(40.0))
+        );
+        assert_eq!(check.check(&reading(2, 40.0)), Ok(()));
+    }
+
+    #[test]
+    fn stuck_value_becomes_stale_then_unreliable() {
+        let mut check = check();
+        for second in 0..=60 {
+            assert_eq!(check.check(&reading(second, 22.5)), Ok(()));
+        }
+
+        for second in 61..64 {
+            assert!(matches!(
+                check.check(&reading(second, 22.5)),
+                Err(ImplausibleReading::Stale(_))
+            ));
+        }
+        assert!(check.is_unreliable());
+
+        assert_eq!(check.check(&reading(64, 22.4375)), Ok(()));
+        assert!(!check.is_unreliable());
+        assert_eq!(check.quarantined_count(), 3);
+    }
+
+    #[test]
+    fn idle_probe_is_never_stale() {
+        let mut check = check();
+        check.set_expecting_change(false);
+        for second in 0..600 {
+            assert_eq!(check.check(&reading(second, 22.5)), Ok(()));
+        }
+
+        check.set_expecting_change(true);
+        for second in 600..=660 {
+            assert_eq!(check.check(&reading(second, 22.5)), Ok(()));
+        }
+        assert!(matches!(
+            check.check(&reading(661, 22.5)),
+            Err(ImplausibleReading::Stale(_))
+        ));
+    }
+
+    #[test]
+    fn coarse_probe_gets_longer_to_change() {
+        let mut check = check();
+        check.set_reading_step(0.5);
+        for second in 0..=480 {
+            assert_eq!(check.check(&reading(second, 22.5)), Ok(()));
+        }
+        assert!(matches!(
+            check.check(&reading(481, 22.5)),
+            Err(ImplausibleReading::Stale(_))
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::Duration;
+
+use log::{error, info};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time::{interval, Interval, MissedTickBehavior};
+
+use crate::devices::sensor_error::SensorError;
+use crate::devices::temperature_source::{TemperatureReading, TemperatureSource};
+use crate::helpers::get_env_or_default;
+
+static IDLE_POLL_INTERVAL_IN_MILLISECONDS_KEY: &str = "IDLE_POLL_INTERVAL_IN_MILLISECONDS";
+static POLL_INTERVAL_IN_MILLISECONDS_KEY: &str = "POLL_INTERVAL_IN_MILLISECONDS";
+static FAST_POLL_INTERVAL_IN_MILLISECONDS_KEY: &str = "FAST_POLL_INTERVAL_IN_MILLISECONDS";
+static FAST_POLLING_TIME_LEFT_IN_SECONDS_KEY: &str = "FAST_POLLING_TIME_LEFT_IN_SECONDS";
+
+const DEFAULT_IDLE_POLL_INTERVAL_IN_MILLISECONDS: u64 = 5000;
+const DEFAULT_POLL_INTERVAL_IN_MILLISECONDS: u64 = 1000;
+const DEFAULT_FAST_POLL_INTERVAL_IN_MILLISECONDS: u64 = 750;
+const DEFAULT_FAST_POLLING_TIME_LEFT_IN_SECONDS: i64 = 60;
+
+/// How often to poll a probe depending on what its bottle is doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PollingPolicy {
+    /// No bottle in progress.
+    pub idle: Duration,
+    /// Bottle heating, cooling or warming.
+    pub active: Duration,
+    /// Bottle less than `fast_below` away from the target.
+    pub fast: Duration,
+    pub fast_below: chrono::Duration,
+}
+
+impl PollingPolicy {
+    pub fn from_env() -> Self {
+        PollingPolicy {
+            idle: Duration::from_millis(get_env_or_default(
+                IDLE_POLL_INTERVAL_IN_MILLISECONDS_KEY,
+                DEFAULT_IDLE_POLL_INTERVAL_IN_MILLISECONDS,
+            )),
+            active: Duration::from_millis(get_env_or_default(
+                POLL_INTERVAL_IN_MILLISECONDS_KEY,
+                DEFAULT_POLL_INTERVAL_IN_MILLISECONDS,
+            )),
+            fast: Duration::from_millis(get_env_or_default(
+                FAST_POLL_INTERVAL_IN_MILLISECONDS_KEY,
+                DEFAULT_FAST_POLL_INTERVAL_IN_MILLISECONDS,
+            )),
+            fast_below: chrono::Duration::seconds(get_env_or_default(
+                FAST_POLLING_TIME_LEFT_IN_SECONDS_KEY,
+                DEFAULT_FAST_POLLING_TIME_LEFT_IN_SECONDS,
+            )),
+        }
+    }
+
+    /// Raises every interval to at least `minimum`, e.g. the probe conversion time.
+    pub fn at_least(self, minimum: Duration) -> Self {
+        PollingPolicy {
+            idle: self.idle.max(minimum),
+            active: self.active.max(minimum),
+            fast: self.fast.max(minimum),
+            fast_below: self.fast_below,
+        }
+    }
+
+    /// Setting name and value of every interval shorter than `minimum`.
+    pub fn shorter_than(&self, minimum: Duration) -> Vec<(&'static str, Duration)> {
+        [
+            (IDLE_POLL_INTERVAL_IN_MILLISECONDS_KEY, self.idle),
+            (POLL_INTERVAL_IN_MILLISECONDS_KEY, self.active),
+            (FAST_POLL_INTERVAL_IN_MILLISECONDS_KEY, self.fast),
+        ]
+        .into_iter()
+        .filter(|(_, interval)| *interval < minimum)
+        .collect()
+    }
+
+    pub fn interval(
+        &self,
+        in_progress: bool,
+        time_to_target: Option<chrono::Duration>,
+    ) -> Duration {
+        match time_to_target {
+            _ if !in_progress => self.idle,
+            Some(time_to_target) if time_to_target < self.fast_below => self.fast,
+            _ => self.active,
+        }
+    }
+}
+
+impl Default for PollingPolicy {
+    fn default() -> Self {
+        PollingPolicy {
+            idle: Duration::from_millis(DEFAULT_IDLE_POLL_INTERVAL_IN_MILLISECONDS),
+            active: Duration::from_millis(DEFAULT_POLL_INTERVAL_IN_MILLISECONDS),
+            fast: Duration::from_millis(DEFAULT_FAST_POLL_INTERVAL_IN_MILLISECONDS),
+            fast_below: chrono::Duration::seconds(DEFAULT_FAST_POLLING_TIME_LEFT_IN_SECONDS),
+        }
+    }
+}
+
+/// Monitoring side handle changing the interval of one running `poll_source`.
+pub struct PollRate {
+    name: String,
+    policy: PollingPolicy,
+    period: watch::Sender<Duration>,
+}
+
+impl PollRate {
+    /// Starts idle and returns the receiver to hand to `poll_source`.
+    pub fn new(name: String, policy: PollingPolicy) -> (Self, watch::Receiver<Duration>) {
+        let (period, receiver) = watch::channel(policy.idle);
+        (
+            PollRate {
+                name,
+                policy,
+                period,
+            },
+            receiver,
+        )
+    }
+
+    pub fn adjust(&self, in_progress: bool, time_to_target: Option<chrono::Duration>) {
+        let period = self.policy.interval(in_progress, time_to_target);
+        if *self.period.borrow() != period {
+            info!("Polling {} every {:?}", self.name, period);
+            self.period.send_replace(period);
+        }
+    }
+}
+
+fn new_ticker(period: Duration) -> Interval {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+/// What a single poll of a source produced, as published to the rest of the app.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorSample {
+    pub identity: String,
+    pub reading: Result<TemperatureReading, SensorError>,
+    pub fault_count: u32,
+    pub attached: bool,
+}
+
+/// Polls `source` every `period` until the receiving side of `sender` is dropped.
+/// A new period applies from the next tick.
+///
+/// Sysfs reads block, so each one runs on tokio's blocking pool and never stalls
+/// the notification or data collection tasks.
+pub async fn poll_source(
+    mut source: Box<dyn TemperatureSource>,
+    period: watch::Receiver<Duration>,
+    sender: Sender<SensorSample>,
+) {
+    let mut current_period = *period.borrow();
+    let mut ticker = new_ticker(current_period);
+
+    loop {
+        ticker.tick().await;
+        let next_period = *period.borrow();
+        if next_period != current_period {
+            current_period = next_period;
+            ticker = new_ticker(current_period);
+            ticker.tick().await;
+        }
+
+        let polled = tokio::task::spawn_blocking(move || {
+            let reading = source.read();
+            let sample = SensorSample {
+                identity: source.identity(),
+                reading,
+                fault_count: source.fault_count(),
+                attached: source.is_attached(),
+            };
+            (source, sample)
+        })
+        .await;
+
+        let sample = match polled {
+            Ok((returned_source, sample)) => {
+                source = returned_source;
+                sample
+            }
+            Err(err) => {
+                error!("Sensor polling stopped: {}", err);
+                return;
+            }
+        };
+
+        if sender.send(sample).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::temperature_source::MockTemperatureSource;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn poll_source_publishes_readings_and_failures() {
+        let mut readings = vec![
+            Ok(TemperatureReading::new(60.0)),
+            Err(SensorError::CrcMismatch),
+        ]
+        .into_iter();
+        let mut source = MockTemperatureSource::new();
+        source
+            .expect_read()
+            .returning(move || readings.next().unwrap_or(Err(SensorError::NotFound)));
+        source.expect_identity().return_const("28-test".to_string());
+        source.expect_fault_count().return_const(1u32);
+        source.expect_is_attached().return_const(true);
+
+        let (sender, mut receiver) = mpsc::channel(4);
+        let (_period, receiver_period) = watch::channel(Duration::from_millis(5));
+        let poller = tokio::spawn(poll_source(Box::new(source), receiver_period, sender));
+
+        let first = receiver.recv().await.unwrap();
+        assert_eq!(first.identity, "28-test");
+        assert_eq!(first.reading.unwrap().temperature, 60.0);
+
+        let second = receiver.recv().await.unwrap();
+        assert_eq!(second.reading, Err(SensorError::CrcMismatch));
+
+        drop(receiver);
+        poller.await.unwrap();
+    }
+
+    #[test]
+    fn polling_interval_follows_the_session_phase() {
+        let policy = PollingPolicy::default();
+
+        assert_eq!(policy.interval(false, None), policy.idle);
+        assert_eq!(policy.interval(true, None), policy.active);
+        assert_eq!(
+            policy.interval(true, Some(chrono::Duration::seconds(300))),
+            policy.active
+        );
+        assert_eq!(
+            policy.interval(true, Some(chrono::Duration::seconds(30))),
+            policy.fast
+        );
+    }
+
+    #[test]
+    fn polling_is_never_faster_than_the_conversion() {
+        let policy = PollingPolicy::default().at_least(Duration::from_millis(750));
+
+        assert_eq!(policy.fast, Duration::from_millis(750));
+        assert_eq!(policy.idle, Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn every_interval_is_checked_against_the_conversion() {
+        let policy = PollingPolicy::default();
+
+        assert_eq!(policy.shorter_than(Duration::from_millis(750)), vec![]);
+        assert_eq!(
+            policy.shorter_than(Duration::from_millis(2000)),
+            vec![
+                (POLL_INTERVAL_IN_MILLISECONDS_KEY, policy.active),
+                (FAST_POLL_INTERVAL_IN_MILLISECONDS_KEY, policy.fast),
+            ]
+        );
+    }
+}
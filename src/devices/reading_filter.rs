@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::helpers::get_env_or_default;
+
+static READING_FILTER_KEY: &str = "READING_FILTER";
+static READING_FILTER_WINDOW_KEY: &str = "READING_FILTER_WINDOW";
+static READING_FILTER_ALPHA_KEY: &str = "READING_FILTER_ALPHA";
+static KALMAN_PROCESS_NOISE_KEY: &str = "KALMAN_PROCESS_NOISE";
+static KALMAN_MEASUREMENT_NOISE_KEY: &str = "KALMAN_MEASUREMENT_NOISE";
+
+const DEFAULT_READING_FILTER_WINDOW: usize = 5;
+const DEFAULT_READING_FILTER_ALPHA: f32 = 0.3;
+const DEFAULT_KALMAN_PROCESS_NOISE: f32 = 0.01;
+const DEFAULT_KALMAN_MEASUREMENT_NOISE: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FilterKind {
+    None,
+    MovingAverage,
+    Exponential,
+    Kalman,
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.trim().to_lowercase().as_str() {
+            "none" => Ok(FilterKind::None),
+            "moving_average" => Ok(FilterKind::MovingAverage),
+            "exponential" => Ok(FilterKind::Exponential),
+            "kalman" => Ok(FilterKind::Kalman),
+            _ => Err(format!("Unknown reading filter: {}", kind)),
+        }
+    }
+}
+
+/// Smooths the quantization noise of raw readings before thresholds and rates use them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReadingFilter {
+    None,
+    MovingAverage {
+        window: usize,
+        values: VecDeque<f32>,
+    },
+    /// `alpha` is the weight of the newest reading, between 0 and 1.
+    Exponential {
+        alpha: f32,
+        value: Option<f32>,
+    },
+    /// One-dimensional Kalman filter assuming a slowly drifting temperature.
+    Kalman {
+        process_noise: f32,
+        measurement_noise: f32,
+        estimate: Option<f32>,
+        error: f32,
+    },
+}
+
+impl ReadingFilter {
+    pub fn from_env() -> Self {
+        match get_env_or_default(READING_FILTER_KEY, FilterKind::None) {
+            FilterKind::None => ReadingFilter::None,
+            FilterKind::MovingAverage => ReadingFilter::moving_average(get_env_or_default(
+                READING_FILTER_WINDOW_KEY,
+                DEFAULT_READING_FILTER_WINDOW,
+            )),
+            FilterKind::Exponential => ReadingFilter::exponential(get_env_or_default(
+                READING_FILTER_ALPHA_KEY,
+                DEFAULT_READING_FILTER_ALPHA,
+            )),
+            FilterKind::Kalman => ReadingFilter::kalman(
+                get_env_or_default(KALMAN_PROCESS_NOISE_KEY, DEFAULT_KALMAN_PROCESS_NOISE),
+                get_env_or_default(
+                    KALMAN_MEASUREMENT_NOISE_KEY,
+                    DEFAULT_KALMAN_MEASUREMENT_NOISE,
+                ),
+            ),
+        }
+    }
+
+    pub fn moving_average(window: usize) -> Self {
+        ReadingFilter::MovingAverage {
+            window: window.max(1),
+            values: VecDeque::new(),
+        }
+    }
+
+    pub fn exponential(alpha: f32) -> Self {
+        ReadingFilter::Exponential {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    pub fn kalman(process_noise: f32, measurement_noise: f32) -> Self {
+        ReadingFilter::Kalman {
+            process_noise,
+            measurement_noise,
+            estimate: None,
+            error: measurement_noise,
+        }
+    }
+
+    /// Feeds a raw reading and returns the filtered temperature.
+    pub fn apply(&mut self, raw_temperature: f32) -> f32 {
+        match self {
+            ReadingFilter::None => raw_temperature,
+            ReadingFilter::MovingAverage { window, values } => {
+                values.push_back(raw_temperature);
+                while values.len() > *window {
+                    values.pop_front();
+                }
+                values.iter().sum::<f32>() / values.len() as f32
+            }
+            ReadingFilter::Exponential { alpha, value } => {
+                let filtered = match value {
+                    Some(previous) => *alpha * raw_temperature + (1.0 - *alpha) * *previous,
+                    None => raw_temperature,
+                };
+                *value = Some(filtered);
+                filtered
+            }
+            ReadingFilter::Kalman {
+                process_noise,
+                measurement_noise,
+                estimate,
+                error,
+            } => {
+                let filtered = match estimate {
+                    Some(previous) => {
+                        *error += *process_noise;
+                        let gain = *error / (*error + *measurement_noise);
+                        *error *= 1.0 - gain;
+                        *previous + gain * (raw_temperature - *previous)
+                    }
+                    None => raw_temperature,
+                };
+                *estimate = Some(filtered);
+                filtered
+            }
+        }
+    }
+
+    /// Forgets the history, e.g. after the probe was off the bus.
+    pub fn reset(&mut self) {
+        match self {
+            ReadingFilter::None => (),
+            ReadingFilter::MovingAverage { values, .. } => values.clear(),
+            ReadingFilter::Exponential { value, .. } => *value = None,
+            ReadingFilter::Kalman {
+                measurement_noise,
+                estimate,
+                error,
+                ..
+            } => {
+                *estimate = None;
+                *error = *measurement_noise;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOISY_READINGS: [f32; 8] = [
+        40.0, 40.0625, 39.9375, 40.0, 40.0625, 39.9375, 40.0, 40.0625,
+    ];
+
+    fn spread(values: &[f32]) -> f32 {
+        let maximum = values.iter().cloned().fold(f32::MIN, f32::max);
+        let minimum = values.iter().cloned().fold(f32::MAX, f32::min);
+        maximum - minimum
+    }
+
+    fn filtered(filter: &mut ReadingFilter) -> Vec<f32> {
+        NOISY_READINGS
+            .iter()
+            .map(|reading| filter.apply(*reading))
+            .collect()
+    }
+
+    #[test]
+    fn none_passes_readings_through() {
+        assert_eq!(filtered(&mut ReadingFilter::None), NOISY_READINGS.to_vec());
+    }
+
+    #[test]
+    fn moving_average_uses_the_last_readings() {
+        let mut filter = ReadingFilter::moving_average(2);
+        assert_eq!(filter.apply(40.0), 40.0);
+        assert_eq!(filter.apply(41.0), 40.5);
+        assert_eq!(filter.apply(43.0), 42.0);
+
+        filter.reset();
+        assert_eq!(filter.apply(30.0), 30.0);
+    }
+
+    #[test]
+    fn filters_reduce_quantization_noise() {
+        for mut filter in [
+            ReadingFilter::moving_average(4),
+            ReadingFilter::exponential(0.3),
+            ReadingFilter::kalman(0.01, 0.25),
+        ] {
+            let values = filtered(&mut filter);
+            assert!(spread(&values[2..]) < spread(&NOISY_READINGS[2..]));
+            assert!((values.last().unwrap() - 40.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn unknown_filter_kind_is_rejected() {
+        assert_eq!("Kalman".parse(), Ok(FilterKind::Kalman));
+        assert!("median".parse::<FilterKind>().is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use core::fmt::Formatter;
+use std::fmt::Display;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SensorError {
+    NotFound,
+    Io(String),
+    Malformed(String),
+    CrcMismatch,
+    PowerOnReset,
+    Disconnected(i32),
+}
+
+impl Display for SensorError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SensorError::NotFound => write!(formatter, "Unable to find temperature sensor"),
+            SensorError::Io(message) => write!(formatter, "I/O error: {}", message),
+            SensorError::Malformed(content) => {
+                write!(formatter, "Malformed sensor output: {:?}", content)
+            }
+            SensorError::CrcMismatch => write!(formatter, "CRC check failed"),
+            SensorError::PowerOnReset => {
+                write!(formatter, "Sensor returned its power-on reset value")
+            }
+            SensorError::Disconnected(millidegrees) => {
+                write!(
+                    formatter,
+                    "Sensor looks disconnected (raw value {})",
+                    millidegrees
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+
+use crate::devices::sensor_error::SensorError;
+
+#[cfg(test)]
+use mockall::automock;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureReading {
+    pub temperature: f32,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TemperatureReading {
+    pub fn new(temperature: f32) -> Self {
+        TemperatureReading {
+            temperature,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+#[cfg_attr(test, automock)]
+pub trait TemperatureSource: Send {
+    /// Reads the current temperature in Celsius.
+    fn read(&mut self) -> Result<TemperatureReading, SensorError>;
+
+    /// Returns a stable identifier for the device, e.g. the 1-Wire serial.
+    fn identity(&self) -> String;
+
+    /// Returns how many reads failed since the source was created.
+    fn fault_count(&self) -> u32;
+
+    /// Returns false while the device is missing from the bus.
+    fn is_attached(&self) -> bool;
+}
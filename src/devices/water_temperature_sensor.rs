@@ -1,191 +1,826 @@
-use chrono::{DateTime, Utc};
+use core::fmt::Formatter;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+
+use crate::cooling_prediction::fit_newton_cooling;
+use crate::cooling_rate::{estimate_cooling_rate, CoolingRate};
+use crate::devices::plausibility::{PlausibilityCheck, PlausibilityPolicy};
+use crate::devices::poller::SensorSample;
+use crate::devices::reading_filter::ReadingFilter;
+use crate::devices::temperature_source::TemperatureReading;
+use crate::helpers::get_prefixed_env_or_default;
+
+const COOLING_RATE_WINDOW_IN_SECONDS: i64 = 300;
+/// The rate and time to target are trusted once the samples cover this much of the window.
+const SAMPLING_READY_SPAN_IN_SECONDS: i64 = 240;
+const SAMPLING_READY_MINIMUM_SAMPLES: usize = 10;
+/// Keeps the window the same size whatever the poll interval.
+const MINIMUM_SAMPLE_SPACING_IN_MILLISECONDS: i64 = 500;
+/// Changes smaller than this are not worth uploading.
+const DATA_COLLECTION_RESOLUTION: f32 = 0.1;
+
+/// A bottle of water cannot cool this fast, a probe moving from water to air can.
+const PROBE_REMOVED_STEP_PER_SECOND: f64 = 2.0;
+/// How many times faster than the fitted cooling curve the drop must be to look like
+/// the probe left the water, ignoring drops slower than the minimum below.
+const PROBE_REMOVED_SLOPE_FACTOR: f64 = 4.0;
+const PROBE_REMOVED_MINIMUM_DROP_PER_SECOND: f64 = 0.2;
+/// Smallest change a 12 bits probe reports, until told otherwise.
+const DEFAULT_READING_STEP: f32 = 0.0625;
+/// Rise that means the probe went back into the water.
+const PROBE_RETURNED_RISE: f32 = 1.0;
+
+static PREPARATION_MODE_KEY: &str = "PREPARATION_MODE";
+static TARGET_TEMPERATURE_KEY: &str = "TARGET_TEMPERATURE";
+static TARGET_HYSTERESIS_KEY: &str = "TARGET_HYSTERESIS";
+static TOO_COLD_TEMPERATURE_KEY: &str = "TOO_COLD_TEMPERATURE";
+static MAXIMUM_TEMPERATURE_KEY: &str = "MAXIMUM_TEMPERATURE";
+
+const DEFAULT_TARGET_TEMPERATURE: f32 = 30.0;
+const DEFAULT_WARMING_TARGET_TEMPERATURE: f32 = 37.0;
+const DEFAULT_TARGET_HYSTERESIS: f32 = 1.0;
+const DEFAULT_TOO_COLD_TEMPERATURE: f32 = 25.0;
+const DEFAULT_MAXIMUM_TEMPERATURE: f32 = 40.0;
+
+/// Whether the bottle is brought to the target from above or from below.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreparationMode {
+    /// Boiled water cooling down to reconstitute formula.
+    Cooling,
+    /// Refrigerated breast milk warming up in a water bath.
+    Warming,
+}
+
+impl FromStr for PreparationMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.trim().to_lowercase().as_str() {
+            "cooling" => Ok(PreparationMode::Cooling),
+            "warming" => Ok(PreparationMode::Warming),
+            _ => Err(format!("Unknown preparation mode: {}", mode)),
+        }
+    }
+}
+
+impl Display for PreparationMode {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreparationMode::Cooling => write!(formatter, "cooling down"),
+            PreparationMode::Warming => write!(formatter, "warming up"),
+        }
+    }
+}
+
+/// The water is armed once it is more than `hysteresis` past the `target` on the
+/// far side and ready once it crosses the target, so noise around the target
+/// cannot flap the state. When cooling, below `too_cold` the powder no longer
+/// dissolves well; when warming, above `maximum` the milk is too hot to serve.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureThreshold {
+    pub mode: PreparationMode,
+    pub target: f32,
+    pub hysteresis: f32,
+    pub too_cold: f32,
+    pub maximum: f32,
+}
+
+impl TemperatureThreshold {
+    /// Reads the thresholds from the settings starting with `prefix`.
+    pub fn from_env(prefix: &str) -> Self {
+        let defaults = match get_prefixed_env_or_default(
+            prefix,
+            PREPARATION_MODE_KEY,
+            PreparationMode::Cooling,
+        ) {
+            PreparationMode::Cooling => TemperatureThreshold::default(),
+            PreparationMode::Warming => TemperatureThreshold::warming(),
+        };
+        TemperatureThreshold {
+            mode: defaults.mode,
+            target: get_prefixed_env_or_default(prefix, TARGET_TEMPERATURE_KEY, defaults.target),
+            hysteresis: get_prefixed_env_or_default(
+                prefix,
+                TARGET_HYSTERESIS_KEY,
+                defaults.hysteresis,
+            )
+            .abs(),
+            too_cold: get_prefixed_env_or_default(
+                prefix,
+                TOO_COLD_TEMPERATURE_KEY,
+                defaults.too_cold,
+            ),
+            maximum: get_prefixed_env_or_default(prefix, MAXIMUM_TEMPERATURE_KEY, defaults.maximum),
+        }
+    }
+
+    /// Defaults for warming refrigerated breast milk to body temperature.
+    pub fn warming() -> Self {
+        TemperatureThreshold {
+            mode: PreparationMode::Warming,
+            target: DEFAULT_WARMING_TARGET_TEMPERATURE,
+            ..TemperatureThreshold::default()
+        }
+    }
+
+    fn arms_at(&self, temperature: f32) -> bool {
+        match self.mode {
+            PreparationMode::Cooling => temperature > self.target + self.hysteresis,
+            PreparationMode::Warming => temperature < self.target - self.hysteresis,
+        }
+    }
+
+    fn is_ready_at(&self, temperature: f32) -> bool {
+        match self.mode {
+            PreparationMode::Cooling => temperature < self.target,
+            PreparationMode::Warming => temperature > self.target,
+        }
+    }
+}
+
+impl Default for TemperatureThreshold {
+    fn default() -> Self {
+        TemperatureThreshold {
+            mode: PreparationMode::Cooling,
+            target: DEFAULT_TARGET_TEMPERATURE,
+            hysteresis: DEFAULT_TARGET_HYSTERESIS,
+            too_cold: DEFAULT_TOO_COLD_TEMPERATURE,
+            maximum: DEFAULT_MAXIMUM_TEMPERATURE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+pub enum SensorEvent {
+    SensorLost,
+    SensorRestored,
+    SensorUnreliable,
+    SensorReliable,
+}
 
 pub struct WaterTemperatureSensor {
+    /// Filtered temperature, used by thresholds and rate estimation.
     pub current_temperature: f32,
-    temperature_filepath: String,
+    raw_temperature: f32,
+    reading_filter: ReadingFilter,
+    reported_temperature: Option<f32>,
+    queued_temperature: Option<f32>,
+    name: String,
+    identity: String,
+    attached: bool,
     last_temperature: f32,
-    temperature_threshold: u8,
+    temperature_threshold: TemperatureThreshold,
     temperature_has_changed: bool,
-    temperature_back_to_normal: bool,
     temperatures_collected_for_rate: Vec<(DateTime<Utc>, f32)>,
+    last_reading_at: Option<DateTime<Utc>>,
+    probe_removed: bool,
+    reading_step: f32,
+    plausibility: PlausibilityCheck,
+    unreliable: bool,
 }
 
-cfg_if::cfg_if! {
-    if #[cfg(test)] {
-        #[cfg(test)]
-        use mockall::automock;
-
-        #[automock]
-        impl WaterTemperatureSensor{
-            pub fn new() -> Self {
-                WaterTemperatureSensor{
-                    temperature_filepath: String::from(""),
-                    current_temperature: 0.0,
-                    last_temperature: 0.0,
-                    temperature_threshold: 30,
-                    temperature_has_changed: false,
-                    temperature_back_to_normal: false,
-                    temperatures_collected_for_rate: Vec::new(),
-                }
-            }
+impl WaterTemperatureSensor {
+    pub fn new(
+        name: String,
+        identity: String,
+        temperature_threshold: TemperatureThreshold,
+        plausibility_policy: PlausibilityPolicy,
+        reading_filter: ReadingFilter,
+    ) -> Self {
+        WaterTemperatureSensor {
+            current_temperature: 0.0,
+            raw_temperature: 0.0,
+            reading_filter,
+            reported_temperature: None,
+            queued_temperature: None,
+            name,
+            identity,
+            attached: true,
+            last_temperature: 0.0,
+            temperature_threshold,
+            temperature_has_changed: false,
+            temperatures_collected_for_rate: Vec::new(),
+            last_reading_at: None,
+            probe_removed: false,
+            reading_step: DEFAULT_READING_STEP,
+            plausibility: PlausibilityCheck::new(plausibility_policy),
+            unreliable: false,
+        }
+    }
 
-            pub fn read(&mut self) -> f32 {
-                self.current_temperature
-            }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-            pub fn should_collect_data(&self) -> bool {
-                self.current_temperature != self.last_temperature
-            }
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
 
-            pub fn is_temperature_back_to_normal(&self) -> bool {
-                self.temperature_back_to_normal
-            }
+    /// Applies a polled sample and reports when the probe left or rejoined the bus,
+    /// or when its readings stopped or started being plausible.
+    pub fn update(&mut self, sample: SensorSample) -> Option<SensorEvent> {
+        match sample.reading {
+            Ok(reading) => match self.plausibility.check(&reading) {
+                Ok(()) => self.record(reading),
+                Err(implausible) => warn!(
+                    "Quarantined reading of {} ({} so far): {}",
+                    self.name,
+                    self.plausibility.quarantined_count(),
+                    implausible
+                ),
+            },
+            Err(err) => error!(
+                "Unable to read temperature from {} ({} faults): {}",
+                self.name, sample.fault_count, err
+            ),
+        }
 
-            pub fn get_temperature_has_changed(&self) -> bool {
-                self.temperature_has_changed
+        let attached = sample.attached;
+        let attachment_event = match (self.attached, attached) {
+            (true, false) => Some(SensorEvent::SensorLost),
+            (false, true) => {
+                self.reading_filter.reset();
+                Some(SensorEvent::SensorRestored)
             }
+            _ => None,
+        };
+        self.attached = attached;
 
-            pub fn reset_temperature_back_to_normal(&mut self) {
-                self.temperature_back_to_normal = false;
-            }
+        let unreliable = self.plausibility.is_unreliable();
+        let reliability_event = match (self.unreliable, unreliable) {
+            (false, true) => Some(SensorEvent::SensorUnreliable),
+            (true, false) => Some(SensorEvent::SensorReliable),
+            _ => None,
+        };
+        self.unreliable = unreliable;
+        attachment_event.or(reliability_event)
+    }
 
-            pub fn is_sampling_ready(&mut self) -> bool {
-                true
-            }
+    /// Readings rejected by the plausibility checks since start.
+    pub fn quarantined_count(&self) -> u32 {
+        self.plausibility.quarantined_count()
+    }
 
-            pub fn flush(&mut self) {
-                self.temperatures_collected_for_rate.clear()
-            }
+    /// True from the moment the water is past the armed temperature until it
+    /// crosses back over the target.
+    pub fn is_armed(&self) -> bool {
+        self.temperature_has_changed
+    }
 
-            pub fn get_cooling_rate_per_sec(&mut self) -> f32{
-                0.0
-            }
+    pub fn mode(&self) -> PreparationMode {
+        self.temperature_threshold.mode
+    }
+
+    pub fn is_too_cold(&self) -> bool {
+        self.temperature_threshold.mode == PreparationMode::Cooling
+            && self.current_temperature < self.temperature_threshold.too_cold
+    }
+
+    /// True when the curve suggests the probe was pulled out of the water, so the
+    /// temperature is the room's rather than the bottle's.
+    pub fn is_probe_removed(&self) -> bool {
+        self.probe_removed
+    }
+
+    /// Smallest change the probe reports, a drop of a single step is quantization
+    /// rather than a probe leaving the water, and a coarse probe repeats the same
+    /// value for longer.
+    pub fn set_reading_step(&mut self, reading_step: f32) {
+        self.reading_step = reading_step;
+        self.plausibility.set_reading_step(reading_step);
+    }
+
+    /// Whether a bottle is in progress, so the water is expected to change.
+    pub fn set_expecting_change(&mut self, expecting_change: bool) {
+        self.plausibility.set_expecting_change(expecting_change);
+    }
+
+    /// True when warmed milk went past the maximum safe temperature.
+    pub fn is_overshooting(&self) -> bool {
+        self.temperature_threshold.mode == PreparationMode::Warming
+            && self.current_temperature > self.temperature_threshold.maximum
+    }
+
+    /// Latest reading before filtering.
+    pub fn raw_temperature(&self) -> f32 {
+        self.raw_temperature
+    }
+
+    /// True when the temperature moved enough since it was last reported, or queued
+    /// to be.
+    pub fn should_collect_data(&self) -> bool {
+        self.queued_temperature
+            .or(self.reported_temperature)
+            .is_none_or(|reported_temperature| {
+                (self.current_temperature - reported_temperature).abs()
+                    >= DATA_COLLECTION_RESOLUTION
+            })
+    }
+
+    /// Remembers the current temperature is waiting to be uploaded, so a slow server
+    /// does not get it queued again.
+    pub fn mark_queued(&mut self) {
+        self.queued_temperature = Some(self.current_temperature);
+    }
+
+    /// Applies the outcome of uploading `temperature`, a failed upload is retried with
+    /// the next reading.
+    pub fn mark_uploaded(&mut self, temperature: f32, uploaded: bool) {
+        if uploaded {
+            self.reported_temperature = Some(temperature);
         }
-    } else {
-        use std::fs;
-
-        use log::{debug, info};
-
-        const BASE_DIR_TEMPERATURE_SENSOR: &str = "/sys/bus/w1/devices/";
-        const SAMPLING_SIZE: usize = 300;
-        const QUERY_DELAY_TIME_IN_SECONDS: u64 = 1;
-
-        impl WaterTemperatureSensor {
-            pub fn new() -> Self {
-                WaterTemperatureSensor {
-                    temperature_filepath: WaterTemperatureSensor::get_temperature_filepath().unwrap(),
-                    current_temperature: 0.0,
-                    last_temperature: 0.0,
-                    temperature_threshold: 30,
-                    temperature_has_changed: false,
-                    temperature_back_to_normal: false,
-                    temperatures_collected_for_rate: Vec::new(),
-                }
-            }
+        if self.queued_temperature == Some(temperature) {
+            self.queued_temperature = None;
+        }
+    }
 
-            pub fn read(&mut self) {
-                debug!("Reading temperature from {}\n", self.temperature_filepath);
-                self.last_temperature = self.current_temperature;
-                self.current_temperature = fs::read_to_string(&self.temperature_filepath)
-                    .unwrap()
-                    .trim()
-                    .parse::<f32>()
-                    .unwrap()
-                    / 1000.0;
-
-                self.set_temperature_has_changed();
-                if self.should_collect_for_sampling()
-                 && self.temperatures_collected_for_rate.len() < SAMPLING_SIZE {
-                    info!("Collecting temperature for sampling");
-                    self.temperatures_collected_for_rate.push((Utc::now(), self.current_temperature));
-                }
-                info!("Current Temperature {}", self.current_temperature);
-                std::thread::sleep(std::time::Duration::from_secs(QUERY_DELAY_TIME_IN_SECONDS));
-            }
+    /// True when the latest reading was collected and the window can be trusted.
+    pub fn is_sampling_ready(&mut self) -> bool {
+        let is_sampling_ready = self.should_collect_for_sampling() && self.has_sampling_window();
+        info!("Is sampling ready for {}: {}", self.name, is_sampling_ready);
+        is_sampling_ready
+    }
 
-            pub fn is_temperature_back_to_normal(&self) -> bool {
-                self.temperature_back_to_normal
-            }
+    /// True once the collected samples cover enough time to trust the rate and time
+    /// to target, even when the latest reading repeated the previous one.
+    pub fn has_sampling_window(&self) -> bool {
+        self.temperatures_collected_for_rate.len() >= SAMPLING_READY_MINIMUM_SAMPLES
+            && self.sampling_span() >= Duration::seconds(SAMPLING_READY_SPAN_IN_SECONDS)
+    }
 
-            pub fn get_temperature_has_changed(&self) -> bool {
-                self.temperature_has_changed
-            }
+    /// Least-squares cooling rate over the last `COOLING_RATE_WINDOW_IN_SECONDS`.
+    pub fn get_cooling_rate(&self) -> Option<CoolingRate> {
+        estimate_cooling_rate(&self.temperatures_collected_for_rate)
+    }
 
-            pub fn reset_temperature_back_to_normal(&mut self) {
-                self.temperature_back_to_normal = false;
-            }
+    /// Predicted time until the water reaches the target, from the latest sample.
+    pub fn get_time_to_target(&self) -> Option<Duration> {
+        let (latest, _) = self.temperatures_collected_for_rate.last()?;
+        fit_newton_cooling(&self.temperatures_collected_for_rate)?
+            .time_to_reach(self.temperature_threshold.target, *latest)
+    }
 
-            pub fn should_collect_data(&self) -> bool {
-                self.current_temperature != self.last_temperature
-            }
+    pub fn flush(&mut self) {
+        self.temperatures_collected_for_rate.clear()
+    }
 
-            pub fn is_sampling_ready(&mut self) -> bool {
-                let mut is_sampling_ready = false;
-                if self.should_collect_for_sampling()
-                 && self.temperatures_collected_for_rate.len() >= SAMPLING_SIZE {
-                    is_sampling_ready = true;
-                }
-                info!("Is sampling ready: {}", is_sampling_ready);
-                is_sampling_ready
-            }
+    /// Applies the thresholds of another profile, starting over from the current temperature.
+    pub fn set_temperature_threshold(&mut self, temperature_threshold: TemperatureThreshold) {
+        self.temperature_threshold = temperature_threshold;
+        self.temperature_has_changed = false;
+        self.flush();
+        self.set_temperature_has_changed();
+    }
 
-            pub fn get_cooling_rate_per_sec(&mut self) -> f32{
-                let first_datetime_temperature = match self.temperatures_collected_for_rate.first() {
-                    Some(first_datetime_temperature) => first_datetime_temperature,
-                    None => return -1.0,
-                };
-                let last_datetime_temperature = match self.temperatures_collected_for_rate.last() {
-                    Some(last_datetime_temperature) => last_datetime_temperature,
-                    None => return -1.0,
-                };
+    fn record(&mut self, reading: TemperatureReading) {
+        self.last_temperature = self.current_temperature;
+        self.raw_temperature = reading.temperature;
+        self.current_temperature = self.reading_filter.apply(reading.temperature);
+        let previous_reading_at = self.last_reading_at.replace(reading.timestamp);
 
-                let time_difference = last_datetime_temperature.0.signed_duration_since(first_datetime_temperature.0).num_seconds() as f32;
-                let temperature_difference = last_datetime_temperature.1 - first_datetime_temperature.1;
+        if let Some(previous_reading_at) = previous_reading_at {
+            self.detect_probe_removal(previous_reading_at, reading.timestamp);
+        }
+        self.set_temperature_has_changed();
+        if self.probe_removed {
+            self.flush();
+        } else if self.should_collect_for_sampling() && self.is_sample_due(reading.timestamp) {
+            info!("Collecting temperature of {} for sampling", self.name);
+            self.temperatures_collected_for_rate
+                .push((reading.timestamp, self.current_temperature));
+            self.slide_sampling_window(reading.timestamp);
+        }
+        info!(
+            "Current Temperature of {}: {}",
+            self.name, self.current_temperature
+        );
+    }
 
-                return temperature_difference / time_difference;
+    /// Flags a cooling probe whose last drop is a sudden step or much steeper than
+    /// the fitted cooling curve allows, and clears the flag once it warms back up.
+    fn detect_probe_removal(&mut self, previous_reading_at: DateTime<Utc>, now: DateTime<Utc>) {
+        if self.probe_removed {
+            if self.current_temperature > self.last_temperature + PROBE_RETURNED_RISE {
+                info!("Probe of {} is back in the water", self.name);
+                self.probe_removed = false;
             }
+            return;
+        }
+        if self.temperature_threshold.mode != PreparationMode::Cooling || !self.is_armed() {
+            return;
+        }
 
-            pub fn flush(&mut self) {
-                self.temperatures_collected_for_rate.clear()
-            }
+        let elapsed = (now - previous_reading_at).num_milliseconds() as f64 / 1000.0;
+        if elapsed <= 0.0 || self.last_temperature - self.current_temperature <= self.reading_step {
+            return;
+        }
+        let observed_rate = (self.current_temperature - self.last_temperature) as f64 / elapsed;
+        let is_sudden_step = observed_rate < -PROBE_REMOVED_STEP_PER_SECOND;
+        let is_off_the_curve = observed_rate < -PROBE_REMOVED_MINIMUM_DROP_PER_SECOND
+            && fit_newton_cooling(&self.temperatures_collected_for_rate).is_some_and(|fit| {
+                let expected_rate =
+                    -fit.cooling_constant * (self.last_temperature as f64 - fit.ambient);
+                observed_rate < expected_rate * PROBE_REMOVED_SLOPE_FACTOR
+            });
+        if is_sudden_step || is_off_the_curve {
+            warn!(
+                "Probe of {} looks out of the water: {} -> {} in {}s",
+                self.name, self.last_temperature, self.current_temperature, elapsed
+            );
+            self.probe_removed = true;
+        }
+    }
 
-            fn should_collect_for_sampling(&self) -> bool {
-                self.current_temperature > self.temperature_threshold as f32
-                 && self.current_temperature < self.last_temperature
-            }
+    fn slide_sampling_window(&mut self, now: DateTime<Utc>) {
+        let window_start = now - Duration::seconds(COOLING_RATE_WINDOW_IN_SECONDS);
+        self.temperatures_collected_for_rate
+            .retain(|(timestamp, _)| *timestamp >= window_start);
+    }
 
-            fn set_temperature_has_changed(&mut self) {
-                let new_temperature_has_changed =
-                    self.current_temperature as u8 > self.temperature_threshold;
-                debug!(
-                    "Temperature has changed: {} -> {}",
-                    self.temperature_has_changed, new_temperature_has_changed
-                );
-                if self.temperature_has_changed && !new_temperature_has_changed {
-                    info!("Temperature back to normal");
-                    self.temperature_back_to_normal = true;
-                }
-                self.temperature_has_changed = new_temperature_has_changed;
+    fn is_sample_due(&self, now: DateTime<Utc>) -> bool {
+        self.temperatures_collected_for_rate
+            .last()
+            .is_none_or(|(collected_at, _)| {
+                now - *collected_at
+                    >= Duration::milliseconds(MINIMUM_SAMPLE_SPACING_IN_MILLISECONDS)
+            })
+    }
+
+    fn sampling_span(&self) -> Duration {
+        match (
+            self.temperatures_collected_for_rate.first(),
+            self.temperatures_collected_for_rate.last(),
+        ) {
+            (Some((first, _)), Some((last, _))) => *last - *first,
+            _ => Duration::zero(),
+        }
+    }
+
+    fn should_collect_for_sampling(&self) -> bool {
+        match self.temperature_threshold.mode {
+            PreparationMode::Cooling => {
+                self.current_temperature > self.temperature_threshold.target
+                    && self.current_temperature < self.last_temperature
             }
+            PreparationMode::Warming => {
+                self.current_temperature < self.temperature_threshold.target
+                    && self.current_temperature > self.last_temperature
+            }
+        }
+    }
+
+    fn set_temperature_has_changed(&mut self) {
+        let new_temperature_has_changed = if self.temperature_has_changed {
+            !self
+                .temperature_threshold
+                .is_ready_at(self.current_temperature)
+        } else {
+            self.temperature_threshold.arms_at(self.current_temperature)
+        };
+        debug!(
+            "Temperature has changed: {} -> {}",
+            self.temperature_has_changed, new_temperature_has_changed
+        );
+        if self.temperature_has_changed && !new_temperature_has_changed {
+            info!("Temperature of {} back to normal", self.name);
+        }
+        self.temperature_has_changed = new_temperature_has_changed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::sensor_error::SensorError;
+
+    fn sample(seconds: i64, temperature: f32) -> SensorSample {
+        SensorSample {
+            identity: "28-test".to_string(),
+            reading: Ok(TemperatureReading {
+                temperature,
+                timestamp: DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(seconds),
+            }),
+            fault_count: 0,
+            attached: true,
+        }
+    }
+
+    /// Most tests jump between temperatures faster than real water would.
+    fn without_slew_limit() -> PlausibilityPolicy {
+        PlausibilityPolicy {
+            maximum_slew_rate_per_second: f32::MAX,
+            ..PlausibilityPolicy::default()
+        }
+    }
+
+    fn failed_sample(attached: bool) -> SensorSample {
+        SensorSample {
+            identity: "28-test".to_string(),
+            reading: Err(SensorError::NotFound),
+            fault_count: 1,
+            attached,
+        }
+    }
+
+    fn sensor_fed_with(temperatures: &[f32]) -> WaterTemperatureSensor {
+        let mut sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            without_slew_limit(),
+            ReadingFilter::None,
+        );
+        for (index, temperature) in temperatures.iter().enumerate() {
+            sensor.update(sample(index as i64, *temperature));
+        }
+        sensor
+    }
+
+    #[test]
+    fn temperature_back_to_normal_when_crossing_threshold_downward() {
+        let mut sensor = sensor_fed_with(&[80.0, 31.0]);
+        assert!(sensor.is_armed());
+
+        sensor.update(sample(2, 29.5));
+        assert!(!sensor.is_armed());
+    }
+
+    #[test]
+    fn temperature_back_to_normal_uses_fractional_target() {
+        let mut sensor = sensor_fed_with(&[80.0, 30.9]);
+        assert!(sensor.is_armed());
+
+        sensor.update(sample(2, 29.9));
+        assert!(!sensor.is_armed());
+    }
+
+    #[test]
+    fn noise_around_target_does_not_rearm() {
+        let mut sensor = sensor_fed_with(&[80.0, 29.9]);
+
+        for (index, temperature) in [30.4, 29.8, 30.6, 29.9].iter().enumerate() {
+            sensor.update(sample(index as i64 + 2, *temperature));
+            assert!(!sensor.is_armed());
+        }
+    }
+
+    #[test]
+    fn failed_read_keeps_last_temperature() {
+        let mut sensor = sensor_fed_with(&[42.0]);
+
+        sensor.update(failed_sample(true));
+        assert_eq!(sensor.current_temperature, 42.0);
+    }
+
+    #[test]
+    fn sensors_track_thresholds_independently() {
+        let left = sensor_fed_with(&[80.0, 29.0]);
+        let right = sensor_fed_with(&[80.0, 60.0]);
+
+        assert!(!left.is_armed());
+        assert!(right.is_armed());
+    }
 
-            fn get_temperature_filepath() -> Result<String, String> {
-                let directories = fs::read_dir(BASE_DIR_TEMPERATURE_SENSOR).unwrap();
-                let mut base_directory_copy = String::from(BASE_DIR_TEMPERATURE_SENSOR);
+    #[test]
+    fn update_reports_sensor_lost_and_restored_once() {
+        let mut sensor = sensor_fed_with(&[50.0]);
 
-                for directory in directories {
-                    let directory = directory.unwrap();
-                    let directory_name = directory.file_name().into_string().unwrap();
+        assert_eq!(
+            sensor.update(failed_sample(false)),
+            Some(SensorEvent::SensorLost)
+        );
+        assert_eq!(sensor.update(failed_sample(false)), None);
+        assert_eq!(
+            sensor.update(sample(3, 50.0)),
+            Some(SensorEvent::SensorRestored)
+        );
+    }
+
+    #[test]
+    fn cooling_rate_uses_reading_timestamps() {
+        let sensor = sensor_fed_with(&[80.0, 79.0, 78.0, 77.0]);
+
+        let cooling_rate = sensor.get_cooling_rate().unwrap();
+        assert!((cooling_rate.rate_per_sec + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cooling_rate_only_uses_the_time_window() {
+        let mut sensor = sensor_fed_with(&[90.0, 89.0]);
+        sensor.update(sample(COOLING_RATE_WINDOW_IN_SECONDS + 100, 60.0));
+        sensor.update(sample(COOLING_RATE_WINDOW_IN_SECONDS + 110, 59.0));
+
+        let cooling_rate = sensor.get_cooling_rate().unwrap();
+        assert_eq!(cooling_rate.sample_count, 2);
+        assert!((cooling_rate.rate_per_sec + 0.1).abs() < 1e-6);
+    }
 
-                    if directory_name.starts_with("28-") {
-                        base_directory_copy.push_str(&directory_name);
-                        base_directory_copy.push_str("/temperature");
-                        info!("Found temperature sensor at {}\n", base_directory_copy);
-                        return Ok(base_directory_copy);
-                    }
+    #[test]
+    fn sampling_is_ready_after_the_same_time_at_any_poll_rate() {
+        for poll_interval_in_milliseconds in [250, 1000, 5000] {
+            let mut sensor = sensor_fed_with(&[]);
+            let mut elapsed = 0;
+            let mut ready_after = None;
+            while ready_after.is_none() && elapsed <= COOLING_RATE_WINDOW_IN_SECONDS * 1000 {
+                let mut sample = sample(0, 90.0 - elapsed as f32 / 10_000.0);
+                if let Ok(reading) = sample.reading.as_mut() {
+                    reading.timestamp += Duration::milliseconds(elapsed);
+                }
+                sensor.update(sample);
+                if sensor.is_sampling_ready() {
+                    ready_after = Some(elapsed);
                 }
-                Err(String::from("Unable to find temperature sensor"))
+                elapsed += poll_interval_in_milliseconds;
             }
 
+            // The first reading has nothing to compare with, so it is not collected.
+            assert_eq!(
+                ready_after,
+                Some(SAMPLING_READY_SPAN_IN_SECONDS * 1000 + poll_interval_in_milliseconds)
+            );
+            assert!(
+                sensor.get_cooling_rate().unwrap().sample_count
+                    <= (SAMPLING_READY_SPAN_IN_SECONDS * 2 + 1) as usize
+            );
+        }
+    }
+
+    #[test]
+    fn time_to_target_follows_the_cooling_curve() {
+        let mut sensor = sensor_fed_with(&[]);
+        for second in 0..120 {
+            let temperature = 20.0 + 40.0 * (-0.005 * second as f32).exp();
+            sensor.update(sample(second, temperature));
+        }
+
+        let time_left = sensor.get_time_to_target().unwrap().num_seconds();
+        // 20 + 40 * exp(-0.005 * t) = 30 at t ~= 277s, 119s already elapsed.
+        assert!((150..170).contains(&time_left));
+    }
+
+    #[test]
+    fn time_to_target_survives_a_repeated_reading() {
+        let mut sensor = sensor_fed_with(&[]);
+        for second in 0..300 {
+            let temperature = 20.0 + 40.0 * (-0.002 * second as f32).exp();
+            sensor.update(sample(second, temperature));
+        }
+        assert!(sensor.is_sampling_ready());
+
+        let last_temperature = sensor.current_temperature;
+        sensor.update(sample(300, last_temperature));
+        assert!(!sensor.is_sampling_ready());
+        assert!(sensor.has_sampling_window());
+        assert!(sensor.get_time_to_target().is_some());
+    }
+
+    #[test]
+    fn warming_mode_is_ready_on_the_way_up() {
+        let mut sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::warming(),
+            without_slew_limit(),
+            ReadingFilter::None,
+        );
+        sensor.update(sample(0, 5.0));
+        assert!(sensor.is_armed());
+        assert!(!sensor.is_too_cold());
+
+        for (index, temperature) in [20.0, 36.5, 36.9].iter().enumerate() {
+            sensor.update(sample(index as i64 + 1, *temperature));
+            assert!(sensor.is_armed());
+        }
+        assert!(sensor.get_cooling_rate().unwrap().rate_per_sec > 0.0);
+
+        sensor.update(sample(4, 37.1));
+        assert!(!sensor.is_armed());
+        assert!(!sensor.is_overshooting());
+
+        sensor.update(sample(5, 40.5));
+        assert!(sensor.is_overshooting());
+    }
+
+    #[test]
+    fn sudden_drop_while_cooling_is_probe_removed() {
+        let mut sensor = sensor_fed_with(&[80.0, 79.9, 79.8]);
+        assert!(!sensor.is_probe_removed());
+
+        sensor.update(sample(3, 70.0));
+        assert!(sensor.is_probe_removed());
+        sensor.update(sample(4, 40.0));
+        sensor.update(sample(5, 24.0));
+        assert!(sensor.is_probe_removed());
+        assert!(!sensor.is_armed());
+
+        sensor.update(sample(6, 75.0));
+        assert!(!sensor.is_probe_removed());
+    }
+
+    #[test]
+    fn drop_steeper_than_the_fitted_curve_is_probe_removed() {
+        let mut sensor = sensor_fed_with(&[]);
+        for second in 0..60 {
+            let temperature = 20.0 + 60.0 * (-0.002 * second as f32).exp();
+            sensor.update(sample(second, temperature));
+        }
+        assert!(!sensor.is_probe_removed());
+
+        let last_temperature = sensor.current_temperature;
+        sensor.update(sample(61, last_temperature - 1.5));
+        assert!(sensor.is_probe_removed());
+        assert_eq!(sensor.get_cooling_rate(), None);
+    }
+
+    #[test]
+    fn single_steps_of_a_low_resolution_probe_are_not_probe_removed() {
+        let mut sensor = sensor_fed_with(&[]);
+        sensor.set_reading_step(0.5);
+        for second in 0..180 {
+            let temperature = 20.0 + 60.0 * (-0.002 * second as f32).exp();
+            sensor.update(sample(second, (temperature * 2.0).round() / 2.0));
+            assert!(!sensor.is_probe_removed());
         }
+
+        let last_temperature = sensor.current_temperature;
+        sensor.update(sample(181, last_temperature - 1.5));
+        assert!(sensor.is_probe_removed());
+    }
+
+    #[test]
+    fn implausible_readings_are_quarantined_until_unreliable() {
+        let mut sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            PlausibilityPolicy {
+                unreliable_after: 2,
+                ..PlausibilityPolicy::default()
+            },
+            ReadingFilter::None,
+        );
+        assert_eq!(sensor.update(sample(0, 22.0)), None);
+
+        assert_eq!(sensor.update(sample(1, 62.0)), None);
+        assert_eq!(sensor.current_temperature, 22.0);
+        assert_eq!(
+            sensor.update(sample(2, 120.0)),
+            Some(SensorEvent::SensorUnreliable)
+        );
+        assert_eq!(sensor.quarantined_count(), 2);
+
+        assert_eq!(
+            sensor.update(sample(3, 22.5)),
+            Some(SensorEvent::SensorReliable)
+        );
+        assert_eq!(sensor.current_temperature, 22.5);
+    }
+
+    #[test]
+    fn thresholds_use_the_filtered_temperature() {
+        let mut sensor = WaterTemperatureSensor::new(
+            "bottle".to_string(),
+            "28-test".to_string(),
+            TemperatureThreshold::default(),
+            without_slew_limit(),
+            ReadingFilter::moving_average(2),
+        );
+        sensor.update(sample(0, 20.0));
+        sensor.update(sample(1, 40.0));
+
+        assert_eq!(sensor.raw_temperature(), 40.0);
+        assert_eq!(sensor.current_temperature, 30.0);
+        assert!(!sensor.is_armed());
+    }
+
+    #[test]
+    fn quantization_noise_is_not_worth_collecting() {
+        let mut sensor = sensor_fed_with(&[40.0]);
+        assert!(sensor.should_collect_data());
+        sensor.mark_uploaded(40.0, true);
+
+        sensor.update(sample(1, 40.0625));
+        assert!(!sensor.should_collect_data());
+        sensor.update(sample(2, 39.9375));
+        assert!(!sensor.should_collect_data());
+
+        sensor.update(sample(3, 40.125));
+        assert!(sensor.should_collect_data());
+    }
+
+    #[test]
+    fn failed_upload_is_collected_again() {
+        let mut sensor = sensor_fed_with(&[40.0]);
+        sensor.mark_queued();
+        assert!(!sensor.should_collect_data());
+
+        sensor.mark_uploaded(40.0, false);
+        assert!(sensor.should_collect_data());
     }
 }
@@ -1,6 +1,9 @@
-use chrono;
-use std::fs::File;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::path::Path;
+use std::str::FromStr;
 
 #[cfg(not(debug_assertions))]
 static LOG_PATH: &str = "/var/log/baby_bottle/";
@@ -24,21 +27,104 @@ pub fn write_to_file(file_path: String, message: String) {
 
 pub fn generate_file_name_with_now_time(extension: String) -> String {
     let local_time = chrono::offset::Local::now();
-    format!("{}{}{}",LOG_PATH, local_time.format("%Y-%m-%d"), extension)
+    format!("{}{}{}", LOG_PATH, local_time.format("%Y-%m-%d"), extension)
+}
+
+/// Parses `key=value,key=value` lists used by the configuration file.
+pub fn parse_key_value_list(raw_list: &str) -> HashMap<String, String> {
+    raw_list
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+        .collect()
+}
+
+/// Reads an optional setting, falling back to `default` when it is unset or empty.
+pub fn get_env_or_default<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value: {}", key, value)),
+        _ => default,
+    }
+}
+
+/// Same as `get_env_or_default` for a key namespaced with `prefix`, e.g. `PROFILE_NIGHT_`.
+pub fn get_prefixed_env_or_default<T: FromStr>(prefix: &str, key: &str, default: T) -> T {
+    get_env_or_default(&format!("{}{}", prefix, key), default)
+}
+
+/// Sets `key=value` in a configuration file, replacing the existing line if any.
+pub fn update_config_value(config_path: &Path, key: &str, value: &str) -> std::io::Result<()> {
+    let content = fs::read_to_string(config_path).unwrap_or_default();
+    let prefix = format!("{}=", key);
+    let mut replaced = false;
+    let mut lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            if line.starts_with(&prefix) {
+                replaced = true;
+                format!("{}{}", prefix, value)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.push(format!("{}{}", prefix, value));
+    }
+    fs::write(config_path, lines.join("\n") + "\n")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
 
     #[test]
     fn test_generate_file_name_with_now_time() {
         let file_name = generate_file_name_with_now_time(".log".to_string());
         let current_time = chrono::offset::Local::now();
-        assert_eq!(file_name.contains(".log"), true);
+        assert!(file_name.contains(".log"));
+        assert!(file_name.contains(&current_time.format("%Y-%m-%d").to_string()));
+    }
+
+    #[test]
+    fn test_parse_key_value_list() {
+        let parsed = parse_key_value_list("28-0000aaa = left, 28-0000bbb=right,broken,=empty");
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed.get("28-0000aaa"), Some(&"left".to_string()));
+        assert_eq!(parsed.get("28-0000bbb"), Some(&"right".to_string()));
+    }
+
+    #[test]
+    fn test_get_env_or_default() {
+        env::set_var("HELPERS_TEST_SET_VALUE", " 31.5 ");
+        env::set_var("HELPERS_TEST_EMPTY_VALUE", "");
+
+        assert_eq!(get_env_or_default("HELPERS_TEST_SET_VALUE", 30.0), 31.5);
+        assert_eq!(get_env_or_default("HELPERS_TEST_EMPTY_VALUE", 30.0), 30.0);
+        assert_eq!(get_env_or_default("HELPERS_TEST_MISSING_VALUE", 30.0), 30.0);
+    }
+
+    #[test]
+    fn test_update_config_value() {
+        let config_directory = TempDir::new().unwrap();
+        let config_path = config_directory.path().join("configs.conf");
+        fs::write(
+            &config_path,
+            "# Probes\nSENSOR_NAMES=\nTARGET_TEMPERATURE=30.0\n",
+        )
+        .unwrap();
+
+        update_config_value(&config_path, "SENSOR_NAMES", "28-aaa=left").unwrap();
+        update_config_value(&config_path, "READING_FILTER", "kalman").unwrap();
+
         assert_eq!(
-            file_name.contains(&current_time.format("%Y-%m-%d").to_string()),
-            true
+            fs::read_to_string(&config_path).unwrap(),
+            "# Probes\nSENSOR_NAMES=28-aaa=left\nTARGET_TEMPERATURE=30.0\nREADING_FILTER=kalman\n"
         );
     }
 }
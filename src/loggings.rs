@@ -1,4 +1,3 @@
-use chrono;
 use log::{self, Level, LevelFilter, Log, SetLoggerError};
 
 use crate::helpers::{generate_file_name_with_now_time, write_to_file};
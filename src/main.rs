@@ -1,16 +1,44 @@
+mod calibrate;
+mod commands;
+mod cooling_prediction;
+mod cooling_rate;
 mod data_collection;
 mod devices;
 mod helpers;
 mod loggings;
+mod notifications;
+mod profile;
+mod session;
 
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use data_collection::collect_data;
-use log::{debug, info};
+use chrono::Utc;
+use commands::{send_command, take_commands, Command, COMMANDS_PATH};
+use cooling_prediction::format_time_left;
+use data_collection::{prepare_data, DataUploader};
+use helpers::{get_env_or_default, parse_key_value_list};
+use log::{debug, error, info, warn};
 use loggings::init_logs;
-use twilio::OutboundMessage;
+use notifications::escalation::Escalations;
+use notifications::{Dispatcher, Event, Notification, Severity};
+use profile::{find_profile, get_sensor_profiles, load_profiles, Profile, DEFAULT_PROFILE_NAME};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::time::interval;
 
-use crate::devices::water_temperature_sensor::WaterTemperatureSensor;
+use crate::devices::calibration::load_calibrations;
+use crate::devices::ds18b20::{Ds18b20, Resolution, BASE_DIR_TEMPERATURE_SENSOR};
+use crate::devices::plausibility::PlausibilityPolicy;
+use crate::devices::poller::{poll_source, PollRate, PollingPolicy, SensorSample};
+use crate::devices::reading_filter::ReadingFilter;
+use crate::devices::temperature_source::TemperatureSource;
+use crate::devices::water_temperature_sensor::{
+    PreparationMode, SensorEvent, WaterTemperatureSensor,
+};
+use crate::session::{BottleSession, SessionState, SessionTransition, TransitionReason};
 
 #[cfg(debug_assertions)]
 const ENVIRONMENT_FILE_PATH: &str = ".env";
@@ -18,74 +46,682 @@ const ENVIRONMENT_FILE_PATH: &str = ".env";
 #[cfg(not(debug_assertions))]
 const ENVIRONMENT_FILE_PATH: &str = "/etc/baby_bottle/configs.conf";
 
+const SAMPLE_CHANNEL_CAPACITY: usize = 32;
+const COMMANDS_POLL_INTERVAL_IN_SECONDS: u64 = 1;
 
-fn get_phone_numbers() -> Vec<String> {
-    env::var("TO_PHONE_NUMBERS")
-        .expect("TO_PHONE_NUMBERS must be set")
-        .split(",")
-        .map(|x| x.trim().to_string())
+static SENSOR_NAMES_KEY: &str = "SENSOR_NAMES";
+static W1_DEVICES_DIR_KEY: &str = "W1_DEVICES_DIR";
+static SENSOR_RESOLUTION_BITS_KEY: &str = "SENSOR_RESOLUTION_BITS";
+static REHEATING_NOTICE_ENABLED_KEY: &str = "REHEATING_NOTICE_ENABLED";
+
+fn get_sensor_names() -> HashMap<String, String> {
+    env::var(SENSOR_NAMES_KEY)
+        .map(|sensor_names| parse_key_value_list(&sensor_names))
+        .unwrap_or_default()
+}
+
+fn get_w1_devices_dir() -> PathBuf {
+    env::var(W1_DEVICES_DIR_KEY)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(BASE_DIR_TEMPERATURE_SENSOR))
+}
+
+/// Queues a notification about the bottle of `bottle_session` for every channel.
+fn notify(
+    dispatcher: &Dispatcher,
+    water_temperature_sensor: &WaterTemperatureSensor,
+    bottle_session: &BottleSession,
+    event: Event,
+    severity: Severity,
+    body: String,
+) {
+    dispatcher.dispatch(
+        Notification::new(event, severity, body)
+            .about(water_temperature_sensor.name())
+            .for_session(bottle_session.id()),
+    );
+}
+
+/// Returns the dispatcher of the profile the bottle is prepared with.
+fn dispatcher_for<'a>(
+    dispatchers: &'a HashMap<String, Dispatcher>,
+    bottle_session: &BottleSession,
+) -> &'a Dispatcher {
+    dispatchers
+        .get(bottle_session.profile_name())
+        .unwrap_or(&dispatchers[DEFAULT_PROFILE_NAME])
+}
+
+/// Returns the profile selected for a sensor by name or serial, the default one otherwise.
+fn profile_for_sensor<'a>(
+    profiles: &'a [Profile],
+    sensor_profiles: &HashMap<String, String>,
+    name: &str,
+    serial: &str,
+) -> &'a Profile {
+    let profile_name = sensor_profiles
+        .get(name)
+        .or_else(|| sensor_profiles.get(serial));
+    match profile_name {
+        Some(profile_name) => find_profile(profiles, profile_name).unwrap_or_else(|| {
+            error!(
+                "Sensor {} uses unknown profile {}, using {} instead",
+                name, profile_name, profiles[0].name
+            );
+            &profiles[0]
+        }),
+        None => &profiles[0],
+    }
+}
+
+/// Starts one polling task per probe and returns the matching monitors, sessions and
+/// poll rates.
+fn init_sensors(
+    sender: Sender<SensorSample>,
+    profiles: &[Profile],
+) -> Vec<(WaterTemperatureSensor, BottleSession, PollRate)> {
+    let sensor_names = get_sensor_names();
+    let sensor_profiles = get_sensor_profiles();
+    let plausibility_policy = PlausibilityPolicy::from_env();
+    let calibrations = load_calibrations();
+    let resolution = get_env_or_default(SENSOR_RESOLUTION_BITS_KEY, Resolution::default());
+    let polling_policy = PollingPolicy::from_env();
+    Ds18b20::discover(&get_w1_devices_dir())
+        .unwrap_or_else(|err| panic!("Unable to open sensor: {}", err))
+        .into_iter()
+        .map(|mut source| {
+            let serial = source.identity();
+            let name = sensor_names.get(&serial).cloned().unwrap_or(serial.clone());
+            if let Some(calibration) = calibrations.get(&serial) {
+                source.set_calibration(*calibration);
+            }
+            if let Err(err) = source.set_resolution(resolution) {
+                warn!("Unable to set the resolution of {}: {}", name, err);
+            }
+            let conversion_time = source.resolution().conversion_time();
+            for (key, interval) in polling_policy.shorter_than(conversion_time) {
+                warn!(
+                    "{} needs {:?} to convert a reading, polling it at that pace instead of every {:?} ({})",
+                    name, conversion_time, interval, key
+                );
+            }
+            let (poll_rate, period) =
+                PollRate::new(name.clone(), polling_policy.at_least(conversion_time));
+            let reading_step = source.resolution().step();
+            tokio::spawn(poll_source(Box::new(source), period, sender.clone()));
+            let profile = profile_for_sensor(profiles, &sensor_profiles, &name, &serial);
+            let mut water_temperature_sensor = WaterTemperatureSensor::new(
+                name,
+                serial.clone(),
+                profile.temperature_threshold,
+                plausibility_policy,
+                ReadingFilter::from_env(),
+            );
+            water_temperature_sensor.set_reading_step(reading_step);
+            (
+                water_temperature_sensor,
+                BottleSession::new(serial, profile),
+                poll_rate,
+            )
+        })
         .collect()
 }
 
-async fn publish_message_to_sms(temperature: f32) -> () {
-    let twilio_account_id = env::var("TWILIO_ACCOUNT_ID").expect("TWILIO_ACCOUNT_ID must be set");
-    let twilio_auth_token = env::var("TWILIO_AUTH_TOKEN").expect("TWILIO_AUTH_TOKEN must be set");
+fn describe_sterilization(bottle_session: &BottleSession) -> String {
+    let sterilization_temperature = bottle_session.sterilization_policy().temperature;
+    let sterilization = bottle_session.sterilization();
+    if bottle_session.is_sterilized() {
+        format!(
+            "sterilized, held above {}C for {}s",
+            sterilization_temperature,
+            sterilization.time_above.num_seconds()
+        )
+    } else if sterilization.reached_at.is_some() {
+        format!(
+            "NOT properly sterilized, only held above {}C for {}s",
+            sterilization_temperature,
+            sterilization.time_above.num_seconds()
+        )
+    } else {
+        format!(
+            "NOT sterilized, never reached {}C",
+            sterilization_temperature
+        )
+    }
+}
 
-    let client = twilio::Client::new(twilio_account_id.as_str(), twilio_auth_token.as_str());
+fn notify_transition(
+    dispatcher: &Dispatcher,
+    water_temperature_sensor: &WaterTemperatureSensor,
+    bottle_session: &mut BottleSession,
+    transition: &SessionTransition,
+) {
+    if bottle_session.take_sterilization_warning(transition) {
+        warn!(
+            "Session {} is cooling without sterilization: {}",
+            transition.session_id,
+            describe_sterilization(bottle_session)
+        );
+        notify(
+            dispatcher,
+            water_temperature_sensor,
+            bottle_session,
+            Event::NotSterilized,
+            Severity::Warning,
+            format!(
+                "Warning: the water on {} is cooling down but was {}",
+                water_temperature_sensor.name(),
+                describe_sterilization(bottle_session)
+            ),
+        );
+    }
 
-    let to_phone_numbers = get_phone_numbers();
-    let from_phone_number = env::var("FROM_PHONE_NUMBER").expect("PHONE_NUMBER must be set");
+    match transition.to {
+        SessionState::Hot
+            if transition.reason == TransitionReason::Reheated
+                && get_env_or_default(REHEATING_NOTICE_ENABLED_KEY, true) =>
+        {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::Reheating,
+                Severity::Info,
+                format!(
+                    "{} is being reheated: {}C, the time estimate starts over",
+                    water_temperature_sensor.name(),
+                    water_temperature_sensor.current_temperature
+                ),
+            );
+        }
+        SessionState::Ready => {
+            debug!("Notifying user ...");
+            let severity = if water_temperature_sensor.is_probe_removed() {
+                Severity::Warning
+            } else {
+                Severity::Info
+            };
+            let message = match water_temperature_sensor.mode() {
+                PreparationMode::Cooling if water_temperature_sensor.is_probe_removed() => format!(
+                    "The probe of {} seems to be out of the water ({}C), check the bottle temperature before using it",
+                    water_temperature_sensor.name(),
+                    water_temperature_sensor.current_temperature
+                ),
+                PreparationMode::Cooling => format!(
+                    "The temperature of {} is {} ({})",
+                    water_temperature_sensor.name(),
+                    water_temperature_sensor.current_temperature,
+                    describe_sterilization(bottle_session)
+                ),
+                PreparationMode::Warming => format!(
+                    "The milk on {} is warm and ready: {}C",
+                    water_temperature_sensor.name(),
+                    water_temperature_sensor.current_temperature
+                ),
+            };
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::BottleReady,
+                severity,
+                message,
+            );
+        }
+        SessionState::Expired => {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::BottleExpired,
+                Severity::Warning,
+                format!(
+                    "The prepared bottle on {} has expired, discard it now",
+                    water_temperature_sensor.name()
+                ),
+            );
+        }
+        _ => (),
+    }
+}
 
-    for to_phone_number in to_phone_numbers {
-        let response = client
-            .send_message(OutboundMessage::new(
-                from_phone_number.as_str(),
-                to_phone_number.as_str(),
-                format!("The temperature is {}", temperature).as_str(),
-            ))
-            .await;
-        debug!("Response: {:?}", response);
+fn report_data(
+    dispatcher: &Dispatcher,
+    uploader: &DataUploader,
+    water_temperature_sensor: &mut WaterTemperatureSensor,
+    bottle_session: &BottleSession,
+    transition: Option<&SessionTransition>,
+) {
+    match prepare_data(
+        water_temperature_sensor,
+        bottle_session,
+        transition,
+        dispatcher.delivery_statuses(),
+    ) {
+        Ok(payload) => {
+            if uploader.upload(payload) {
+                water_temperature_sensor.mark_queued();
+            }
+        }
+        Err(err) => {
+            debug!("Data collection error: {}", err);
+        }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    dotenv::from_filename(ENVIRONMENT_FILE_PATH).ok();
-    init_logs().unwrap_or_else(|_| panic!("Unable to initialize logs"));
+fn monitor(
+    dispatcher: &Dispatcher,
+    uploader: &DataUploader,
+    water_temperature_sensor: &mut WaterTemperatureSensor,
+    bottle_session: &mut BottleSession,
+    poll_rate: &PollRate,
+    sample: SensorSample,
+) {
+    water_temperature_sensor.set_expecting_change(bottle_session.is_in_progress());
+    match water_temperature_sensor.update(sample) {
+        Some(SensorEvent::SensorLost) => {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::SensorLost,
+                Severity::Critical,
+                format!(
+                    "Sensor {} was lost, the temperature is no longer monitored",
+                    water_temperature_sensor.name()
+                ),
+            );
+        }
+        Some(SensorEvent::SensorRestored) => {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::SensorRestored,
+                Severity::Info,
+                format!(
+                    "Sensor {} is back, the temperature is monitored again",
+                    water_temperature_sensor.name()
+                ),
+            );
+        }
+        Some(SensorEvent::SensorUnreliable) => {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::SensorUnreliable,
+                Severity::Critical,
+                format!(
+                    "Sensor {} is unreliable, its readings are rejected ({} so far), \
+                     check the probe",
+                    water_temperature_sensor.name(),
+                    water_temperature_sensor.quarantined_count()
+                ),
+            );
+        }
+        Some(SensorEvent::SensorReliable) => {
+            notify(
+                dispatcher,
+                water_temperature_sensor,
+                bottle_session,
+                Event::SensorReliable,
+                Severity::Info,
+                format!(
+                    "Sensor {} reads plausible values again",
+                    water_temperature_sensor.name()
+                ),
+            );
+        }
+        None => (),
+    }
 
-    let mut phone_notified = false;
+    let now = Utc::now();
+    let transition = bottle_session.update(water_temperature_sensor, now);
+    if let Some(transition) = &transition {
+        if transition.reason == TransitionReason::Reheated {
+            water_temperature_sensor.flush();
+        }
+        if matches!(
+            transition.reason,
+            TransitionReason::Heated | TransitionReason::StartedWarming
+        ) {
+            // The alerts about the previous bottle no longer matter.
+            dispatcher.resolve_bottle(water_temperature_sensor.name());
+        }
+        notify_transition(
+            dispatcher,
+            water_temperature_sensor,
+            bottle_session,
+            transition,
+        );
+    }
+    if let Some(time_since_ready) =
+        bottle_session.take_too_cold_alert(water_temperature_sensor, now)
+    {
+        notify(
+            dispatcher,
+            water_temperature_sensor,
+            bottle_session,
+            Event::WaterTooCold,
+            Severity::Warning,
+            format!(
+                "The water on {} is now too cold to dissolve the powder well: {}C, \
+                 ready {} minutes ago",
+                water_temperature_sensor.name(),
+                water_temperature_sensor.current_temperature,
+                time_since_ready.num_minutes()
+            ),
+        );
+    }
+    if bottle_session.take_overshoot_alert(water_temperature_sensor) {
+        notify(
+            dispatcher,
+            water_temperature_sensor,
+            bottle_session,
+            Event::MilkTooHot,
+            Severity::Critical,
+            format!(
+                "Warning: the milk on {} is too hot: {}C, let it cool before feeding",
+                water_temperature_sensor.name(),
+                water_temperature_sensor.current_temperature
+            ),
+        );
+    }
+    if let Some(time_left) = bottle_session.take_expiry_reminder(now) {
+        notify(
+            dispatcher,
+            water_temperature_sensor,
+            bottle_session,
+            Event::BottleExpiring,
+            Severity::Info,
+            format!(
+                "The prepared bottle on {} should be used soon, {}",
+                water_temperature_sensor.name(),
+                format_time_left(time_left)
+            ),
+        );
+    }
 
-    let mut water_temperature_sensor = WaterTemperatureSensor::new();
-    loop {
-        water_temperature_sensor.read();
+    report_data(
+        dispatcher,
+        uploader,
+        water_temperature_sensor,
+        bottle_session,
+        transition.as_ref(),
+    );
 
-        match collect_data(&water_temperature_sensor).await {
-            Ok(status_code) => {
-                debug!("Data collection status code: {}", status_code);
+    // Kept between collected readings so a flat reading does not slow polling down.
+    let time_to_target = if matches!(
+        bottle_session.state(),
+        SessionState::Cooling | SessionState::Warming
+    ) && water_temperature_sensor.has_sampling_window()
+    {
+        water_temperature_sensor.get_time_to_target()
+    } else {
+        None
+    };
+    if water_temperature_sensor.is_sampling_ready() {
+        if let Some(cooling_rate) = water_temperature_sensor.get_cooling_rate() {
+            info!(
+                "Cooling rate of {}: {:.4} C/s (r2 {:.2}, {} samples)",
+                water_temperature_sensor.name(),
+                cooling_rate.rate_per_sec,
+                cooling_rate.r_squared,
+                cooling_rate.sample_count
+            );
+        }
+
+        if let Some(time_left) = water_temperature_sensor.get_time_to_target() {
+            info!(
+                "Time to target of {}: {}s",
+                water_temperature_sensor.name(),
+                time_left.num_seconds()
+            );
+            if bottle_session.take_time_left_announcement() {
+                notify(
+                    dispatcher,
+                    water_temperature_sensor,
+                    bottle_session,
+                    Event::TimeLeft,
+                    Severity::Info,
+                    format!(
+                        "{} is {}, {}",
+                        water_temperature_sensor.name(),
+                        water_temperature_sensor.mode(),
+                        format_time_left(time_left)
+                    ),
+                );
             }
-            Err(err) => {
-                debug!("Data collection error: {}", err);
+        }
+    }
+    poll_rate.adjust(bottle_session.is_in_progress(), time_to_target);
+}
+
+fn apply_command(
+    profiles: &[Profile],
+    dispatchers: &HashMap<String, Dispatcher>,
+    uploader: &DataUploader,
+    water_temperature_sensors: &mut [WaterTemperatureSensor],
+    bottle_sessions: &mut [BottleSession],
+    command: Command,
+) {
+    let sensor_name = match &command {
+        Command::Consumed(sensor_name)
+        | Command::Profile(_, sensor_name)
+        | Command::Acknowledge(sensor_name) => sensor_name.clone(),
+    };
+    for (water_temperature_sensor, bottle_session) in water_temperature_sensors
+        .iter_mut()
+        .zip(bottle_sessions.iter_mut())
+    {
+        if sensor_name
+            .as_deref()
+            .is_some_and(|sensor_name| sensor_name != water_temperature_sensor.name())
+        {
+            continue;
+        }
+        match &command {
+            Command::Consumed(_) => {
+                // Even an expired bottle is no longer worth escalating once thrown away.
+                let dispatcher = dispatcher_for(dispatchers, bottle_session);
+                dispatcher.resolve_bottle(water_temperature_sensor.name());
+                if let Some(transition) = bottle_session.mark_consumed(Utc::now()) {
+                    report_data(
+                        dispatcher,
+                        uploader,
+                        water_temperature_sensor,
+                        bottle_session,
+                        Some(&transition),
+                    );
+                }
+            }
+            Command::Profile(profile_name, _) => {
+                let profile = match find_profile(profiles, profile_name) {
+                    Some(profile) => profile,
+                    None => {
+                        warn!("Unknown profile {}", profile_name);
+                        return;
+                    }
+                };
+                if bottle_session.select_profile(profile) {
+                    water_temperature_sensor
+                        .set_temperature_threshold(profile.temperature_threshold);
+                    info!(
+                        "Sensor {} now uses profile {}",
+                        water_temperature_sensor.name(),
+                        profile.name
+                    );
+                } else {
+                    warn!(
+                        "Sensor {} is preparing a bottle, keeping profile {}",
+                        water_temperature_sensor.name(),
+                        bottle_session.profile_name()
+                    );
+                }
             }
+            // Alerts are acknowledged by the dispatchers, the sessions are left as they are.
+            Command::Acknowledge(_) => {}
         }
+    }
+}
 
-        if phone_notified && water_temperature_sensor.is_temperature_back_to_normal() {
-            debug!("Resetting the flags ...");
-            phone_notified = false;
-            water_temperature_sensor.reset_temperature_back_to_normal()
-        } else if water_temperature_sensor.is_temperature_back_to_normal() && !phone_notified {
-            debug!("Notifying user ...");
-            publish_message_to_sms(water_temperature_sensor.current_temperature).await;
-            phone_notified = true;
+/// Handles `baby-bottle-temperature-monitor <command>` invocations, returns false
+/// when the daemon should start instead.
+fn run_cli_command() -> bool {
+    let mut arguments = env::args().skip(1);
+    match arguments.next().as_deref() {
+        None => false,
+        Some("consumed") => {
+            let command = Command::Consumed(arguments.next());
+            send_command(Path::new(COMMANDS_PATH), &command)
+                .unwrap_or_else(|err| panic!("Unable to send {:?}: {}", command, err));
+            println!("Marked the prepared bottle as used");
+            true
+        }
+        Some("profile") => {
+            let profile_name = arguments.next().unwrap_or_else(|| {
+                eprintln!("Missing profile name, expected: profile <profile name> [sensor name]");
+                std::process::exit(2);
+            });
+            let command = Command::Profile(profile_name, arguments.next());
+            send_command(Path::new(COMMANDS_PATH), &command)
+                .unwrap_or_else(|err| panic!("Unable to send {:?}: {}", command, err));
+            println!("The next bottle will be prepared with the selected profile");
+            true
+        }
+        Some("ack") => {
+            let command = Command::Acknowledge(arguments.next());
+            send_command(Path::new(COMMANDS_PATH), &command)
+                .unwrap_or_else(|err| panic!("Unable to send {:?}: {}", command, err));
+            println!("Acknowledged the alerts, they will not be escalated further");
+            true
+        }
+        Some("calibrate") => {
+            dotenv::from_filename(ENVIRONMENT_FILE_PATH).ok();
+            calibrate::run_calibration(
+                &get_w1_devices_dir(),
+                Path::new(ENVIRONMENT_FILE_PATH),
+                arguments.next(),
+            )
+            .unwrap_or_else(|err| {
+                eprintln!("Calibration failed: {}", err);
+                std::process::exit(1);
+            });
+            true
         }
+        Some(unknown) => {
+            eprintln!(
+                "Unknown command {}, expected: consumed [sensor name], profile <profile name> [sensor name], ack [sensor name] or calibrate [serial]",
+                unknown
+            );
+            std::process::exit(2);
+        }
+    }
+}
 
-        if water_temperature_sensor.is_sampling_ready() {
-            let cooling_rate = water_temperature_sensor.get_cooling_rate_per_sec();
-            if cooling_rate > 0.0 {
-                water_temperature_sensor.flush();
-                info!("Temperature is cooling down");
-            }
+#[tokio::main]
+async fn main() {
+    if run_cli_command() {
+        return;
+    }
+
+    dotenv::from_filename(ENVIRONMENT_FILE_PATH).ok();
+    init_logs().unwrap_or_else(|_| panic!("Unable to initialize logs"));
 
-            info!("Cooling rate: {}", cooling_rate);
+    let (sender, mut receiver) = mpsc::channel(SAMPLE_CHANNEL_CAPACITY);
+    let profiles = load_profiles();
+    // A probe switching profile must still resolve the alerts it raised before.
+    let escalations = Arc::new(Mutex::new(Escalations::from_env()));
+    let dispatchers: HashMap<String, Dispatcher> = profiles
+        .iter()
+        .map(|profile| {
+            (
+                profile.name.clone(),
+                Dispatcher::for_profile(profile, escalations.clone()),
+            )
+        })
+        .collect();
+    let (uploader, mut upload_reports) = DataUploader::spawn();
+    let mut water_temperature_sensors = Vec::new();
+    let mut bottle_sessions = Vec::new();
+    let mut poll_rates = Vec::new();
+    for (water_temperature_sensor, bottle_session, poll_rate) in init_sensors(sender, &profiles) {
+        water_temperature_sensors.push(water_temperature_sensor);
+        bottle_sessions.push(bottle_session);
+        poll_rates.push(poll_rate);
+    }
+    for (water_temperature_sensor, bottle_session) in
+        water_temperature_sensors.iter().zip(bottle_sessions.iter())
+    {
+        info!(
+            "Monitoring sensor {} ({}) with profile {}",
+            water_temperature_sensor.name(),
+            water_temperature_sensor.identity(),
+            bottle_session.profile_name()
+        );
+    }
+
+    let mut commands_ticker = interval(Duration::from_secs(COMMANDS_POLL_INTERVAL_IN_SECONDS));
+    loop {
+        tokio::select! {
+            sample = receiver.recv() => {
+                let sample = match sample {
+                    Some(sample) => sample,
+                    None => break,
+                };
+                let monitored_sensor = water_temperature_sensors
+                    .iter_mut()
+                    .zip(bottle_sessions.iter_mut())
+                    .zip(poll_rates.iter())
+                    .find(|((water_temperature_sensor, _), _)| {
+                        water_temperature_sensor.identity() == sample.identity
+                    });
+                if let Some(((water_temperature_sensor, bottle_session), poll_rate)) =
+                    monitored_sensor
+                {
+                    let dispatcher = dispatcher_for(&dispatchers, bottle_session);
+                    monitor(
+                        dispatcher,
+                        &uploader,
+                        water_temperature_sensor,
+                        bottle_session,
+                        poll_rate,
+                        sample,
+                    );
+                }
+            }
+            Some(upload_report) = upload_reports.recv() => {
+                if let Some(water_temperature_sensor) = water_temperature_sensors
+                    .iter_mut()
+                    .find(|water_temperature_sensor| {
+                        water_temperature_sensor.identity() == upload_report.sensor_serial
+                    })
+                {
+                    water_temperature_sensor
+                        .mark_uploaded(upload_report.temperature, upload_report.uploaded);
+                }
+            }
+            _ = commands_ticker.tick() => {
+                for command in take_commands(Path::new(COMMANDS_PATH)) {
+                    // The escalations are shared, any dispatcher reaches those of every profile.
+                    if let Command::Acknowledge(sensor_name) = &command {
+                        dispatchers[DEFAULT_PROFILE_NAME].acknowledge(sensor_name.as_deref());
+                    }
+                    apply_command(
+                        &profiles,
+                        &dispatchers,
+                        &uploader,
+                        &mut water_temperature_sensors,
+                        &mut bottle_sessions,
+                        command,
+                    );
+                }
+                dispatchers[DEFAULT_PROFILE_NAME].escalate(Utc::now());
+            }
         }
     }
+    for dispatcher in dispatchers.into_values() {
+        dispatcher.close().await;
+    }
 }
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+
+use crate::notifications::twilio_call::TwilioCall;
+use crate::notifications::twilio_sms::TwilioSms;
+use crate::notifications::{Delivery, Event, Notification, Notifier};
+use crate::profile::parse_phone_numbers;
+
+static ESCALATION_KEY_PREFIX: &str = "ESCALATION_";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TierChannel {
+    Sms,
+    Call,
+}
+
+impl FromStr for TierChannel {
+    type Err = String;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel.trim().to_lowercase().as_str() {
+            "sms" => Ok(TierChannel::Sms),
+            "call" => Ok(TierChannel::Call),
+            _ => Err(format!("Unknown escalation channel: {}", channel)),
+        }
+    }
+}
+
+/// One configured step, `<minutes>:<sms|call>:<phone numbers>`, reached when the
+/// alert is still not acknowledged `after` it was first sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TierSpec {
+    pub after: Duration,
+    pub channel: TierChannel,
+    pub recipients: Vec<String>,
+}
+
+/// Parses `;` separated tiers, which must come in increasing delay order.
+pub fn parse_tiers(raw_tiers: &str) -> Result<Vec<TierSpec>, String> {
+    let mut tiers: Vec<TierSpec> = Vec::new();
+    for raw_tier in raw_tiers.split(';').filter(|tier| !tier.trim().is_empty()) {
+        let invalid = || format!("Invalid escalation tier: {}", raw_tier);
+        let mut fields = raw_tier.splitn(3, ':');
+        let minutes: i64 = fields
+            .next()
+            .and_then(|minutes| minutes.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let channel: TierChannel = fields.next().ok_or_else(invalid)?.parse()?;
+        let recipients = parse_phone_numbers(fields.next().unwrap_or_default());
+        if minutes <= 0 || recipients.is_empty() {
+            return Err(invalid());
+        }
+        let after = Duration::minutes(minutes);
+        if tiers.last().is_some_and(|previous| previous.after >= after) {
+            return Err(format!(
+                "Escalation tiers must be in increasing order: {}",
+                raw_tiers
+            ));
+        }
+        tiers.push(TierSpec {
+            after,
+            channel,
+            recipients,
+        });
+    }
+    Ok(tiers)
+}
+
+pub struct EscalationTier {
+    pub after: Duration,
+    pub notifier: Arc<dyn Notifier>,
+}
+
+impl EscalationTier {
+    fn from_spec(spec: TierSpec) -> Self {
+        let notifier: Arc<dyn Notifier> = match spec.channel {
+            TierChannel::Sms => Arc::new(TwilioSms::from_env(spec.recipients)),
+            TierChannel::Call => Arc::new(TwilioCall::from_env(spec.recipients)),
+        };
+        EscalationTier {
+            after: spec.after,
+            notifier,
+        }
+    }
+}
+
+struct PendingEscalation {
+    event: Event,
+    notification: Notification,
+    raised_at: DateTime<Utc>,
+    next_tier: usize,
+}
+
+/// Alerts waiting for someone to acknowledge them, and the tiers they go through
+/// meanwhile, per event.
+#[derive(Default)]
+pub struct Escalations {
+    policies: HashMap<Event, Vec<EscalationTier>>,
+    pending: Vec<PendingEscalation>,
+}
+
+impl Escalations {
+    pub fn new(policies: HashMap<Event, Vec<EscalationTier>>) -> Self {
+        Escalations {
+            policies,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Reads every `ESCALATION_<EVENT>` setting.
+    pub fn from_env() -> Self {
+        let policies = env::vars()
+            .filter_map(|(key, raw_tiers)| {
+                let event: Event = key
+                    .strip_prefix(ESCALATION_KEY_PREFIX)?
+                    .parse()
+                    .unwrap_or_else(|err| panic!("{}: {}", key, err));
+                let tiers =
+                    parse_tiers(&raw_tiers).unwrap_or_else(|err| panic!("{}: {}", key, err));
+                (!tiers.is_empty()).then(|| {
+                    (
+                        event,
+                        tiers.into_iter().map(EscalationTier::from_spec).collect(),
+                    )
+                })
+            })
+            .collect();
+        Escalations::new(policies)
+    }
+
+    /// Starts escalating `notification` when its event has a policy. A new alert of
+    /// the same event and sensor replaces the pending one.
+    pub fn raise(&mut self, notification: &Notification, now: DateTime<Utc>) {
+        let event = notification.event;
+        if !self.policies.contains_key(&event) {
+            return;
+        }
+        self.pending.retain(|pending| {
+            pending.event != event || pending.notification.sensor_name != notification.sensor_name
+        });
+        self.pending.push(PendingEscalation {
+            event,
+            notification: notification.clone(),
+            raised_at: now,
+            next_tier: 0,
+        });
+    }
+
+    /// Cancels the pending escalations `notification` puts an end to, e.g. a lost
+    /// sensor once it is restored.
+    pub fn resolve(&mut self, notification: &Notification) -> usize {
+        match notification.sensor_name.as_deref() {
+            Some(sensor_name) => self.cancel(Some(sensor_name), |event| {
+                notification.event.resolves().contains(&event)
+            }),
+            None => 0,
+        }
+    }
+
+    /// Cancels the pending escalations of `sensor_name`, or all of them, and returns
+    /// how many were cancelled.
+    pub fn acknowledge(&mut self, sensor_name: Option<&str>) -> usize {
+        self.cancel(sensor_name, |_| true)
+    }
+
+    /// Cancels the pending escalations of `sensor_name`, or of every sensor, whose
+    /// event `is_cancelled`, and returns how many were cancelled.
+    pub fn cancel(
+        &mut self,
+        sensor_name: Option<&str>,
+        is_cancelled: impl Fn(Event) -> bool,
+    ) -> usize {
+        let pending_count = self.pending.len();
+        self.pending.retain(|pending| {
+            let is_of_sensor = sensor_name.is_none_or(|sensor_name| {
+                pending.notification.sensor_name.as_deref() == Some(sensor_name)
+            });
+            !(is_of_sensor && is_cancelled(pending.event))
+        });
+        pending_count - self.pending.len()
+    }
+
+    /// Returns the deliveries of every tier that became due and forgets the alerts
+    /// with no tier left.
+    pub fn escalate(&mut self, now: DateTime<Utc>) -> Vec<Delivery> {
+        let mut deliveries = Vec::new();
+        let Escalations { policies, pending } = self;
+        for escalation in pending.iter_mut() {
+            let tiers = match policies.get(&escalation.event) {
+                Some(tiers) => tiers,
+                None => continue,
+            };
+            while let Some(tier) = tiers.get(escalation.next_tier) {
+                if now - escalation.raised_at < tier.after {
+                    break;
+                }
+                escalation.next_tier += 1;
+                let notification = Notification {
+                    body: format!(
+                        "Not acknowledged after {} minutes: {}",
+                        tier.after.num_minutes(),
+                        escalation.notification.body
+                    ),
+                    ..escalation.notification.clone()
+                };
+                info!(
+                    "Escalating {:?} through {}",
+                    escalation.notification.title,
+                    tier.notifier.name()
+                );
+                deliveries.push(Delivery {
+                    notification,
+                    notifiers: vec![tier.notifier.clone()],
+                });
+            }
+        }
+        pending.retain(|escalation| {
+            policies
+                .get(&escalation.event)
+                .is_some_and(|tiers| escalation.next_tier < tiers.len())
+        });
+        deliveries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::recording_notifier::RecordingNotifier;
+    use crate::notifications::{deliver, Severity};
+    use std::sync::Mutex;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minutes)
+    }
+
+    fn too_cold(sensor_name: &str) -> Notification {
+        Notification::new(
+            Event::WaterTooCold,
+            Severity::Warning,
+            "The water is too cold".to_string(),
+        )
+        .about(sensor_name)
+    }
+
+    type Sent = Arc<Mutex<Vec<Notification>>>;
+
+    /// Two tiers, at 10 and 20 minutes, for water too cold.
+    fn escalations() -> (Escalations, Sent, Sent) {
+        let second = RecordingNotifier::new("sms", Ok(()));
+        let third = RecordingNotifier::new("call", Ok(()));
+        let (second_sent, third_sent) = (second.sent(), third.sent());
+        let policies = HashMap::from([(
+            Event::WaterTooCold,
+            vec![
+                EscalationTier {
+                    after: Duration::minutes(10),
+                    notifier: Arc::new(second),
+                },
+                EscalationTier {
+                    after: Duration::minutes(20),
+                    notifier: Arc::new(third),
+                },
+            ],
+        )]);
+        (Escalations::new(policies), second_sent, third_sent)
+    }
+
+    async fn escalate(escalations: &mut Escalations, now: DateTime<Utc>) {
+        for delivery in escalations.escalate(now) {
+            deliver(&delivery).await;
+        }
+    }
+
+    #[test]
+    fn tiers_are_parsed_in_order() {
+        assert_eq!(
+            parse_tiers("10:sms:+15550002; 20:call:+15550001,+15550002"),
+            Ok(vec![
+                TierSpec {
+                    after: Duration::minutes(10),
+                    channel: TierChannel::Sms,
+                    recipients: vec!["+15550002".to_string()],
+                },
+                TierSpec {
+                    after: Duration::minutes(20),
+                    channel: TierChannel::Call,
+                    recipients: vec!["+15550001".to_string(), "+15550002".to_string()],
+                },
+            ])
+        );
+        assert_eq!(parse_tiers(""), Ok(vec![]));
+        assert!(parse_tiers("10:pager:+15550002").is_err());
+        assert!(parse_tiers("10:sms:").is_err());
+        assert!(parse_tiers("20:sms:+15550002;10:call:+15550001").is_err());
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_alert_goes_through_every_tier() {
+        let (mut escalations, second_sent, third_sent) = escalations();
+        escalations.raise(&too_cold("left"), at(0));
+
+        escalate(&mut escalations, at(9)).await;
+        assert!(second_sent.lock().unwrap().is_empty());
+
+        escalate(&mut escalations, at(10)).await;
+        assert_eq!(
+            second_sent.lock().unwrap()[0].body,
+            "Not acknowledged after 10 minutes: The water is too cold"
+        );
+        assert!(third_sent.lock().unwrap().is_empty());
+
+        escalate(&mut escalations, at(25)).await;
+        escalate(&mut escalations, at(30)).await;
+        assert_eq!(second_sent.lock().unwrap().len(), 1);
+        assert_eq!(third_sent.lock().unwrap().len(), 1);
+        assert!(escalations.pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn acknowledgement_cancels_the_escalation() {
+        let (mut escalations, second_sent, _) = escalations();
+        escalations.raise(&too_cold("left"), at(0));
+        escalations.raise(&too_cold("right"), at(0));
+
+        assert_eq!(escalations.acknowledge(Some("left")), 1);
+        escalate(&mut escalations, at(10)).await;
+        assert_eq!(second_sent.lock().unwrap().len(), 1);
+        assert_eq!(
+            second_sent.lock().unwrap()[0].sensor_name.as_deref(),
+            Some("right")
+        );
+
+        assert_eq!(escalations.acknowledge(None), 1);
+        escalate(&mut escalations, at(20)).await;
+        assert_eq!(second_sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn events_without_policy_are_not_escalated() {
+        let (mut escalations, _, _) = escalations();
+        escalations.raise(
+            &Notification::new(Event::TimeLeft, Severity::Info, "5 minutes".to_string()),
+            at(0),
+        );
+
+        assert!(escalations.pending.is_empty());
+    }
+}
@@ -0,0 +1,27 @@
+use log::{info, warn};
+
+use crate::notifications::{Notification, Notifier, SendFuture, Severity};
+
+/// Writes notifications to the application log, e.g. while SMS are not set up.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        let session = notification.session_id.as_deref().unwrap_or("-");
+        match notification.severity {
+            Severity::Info => info!(
+                "Notification {} (session {}): {}",
+                notification.title, session, notification.body
+            ),
+            Severity::Warning | Severity::Critical => warn!(
+                "Notification {} [{}] (session {}): {}",
+                notification.title, notification.severity, session, notification.body
+            ),
+        }
+        Box::pin(async { Ok(()) })
+    }
+}
@@ -0,0 +1,605 @@
+use core::fmt::Formatter;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+use crate::helpers::get_env_or_default;
+use crate::profile::Profile;
+
+pub mod escalation;
+pub mod log_notifier;
+#[cfg(test)]
+pub mod recording_notifier;
+pub mod twilio_call;
+pub mod twilio_sms;
+
+use escalation::Escalations;
+use log_notifier::LogNotifier;
+use twilio_sms::TwilioSms;
+
+static NOTIFICATION_CHANNELS_KEY: &str = "NOTIFICATION_CHANNELS";
+
+const DEFAULT_NOTIFICATION_CHANNELS: &str = "sms";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl Display for Severity {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(formatter, "info"),
+            Severity::Warning => write!(formatter, "warning"),
+            Severity::Critical => write!(formatter, "critical"),
+        }
+    }
+}
+
+/// What a notification is about, the part of the settings that refer to it, e.g.
+/// `ESCALATION_WATER_TOO_COLD`, does not depend on the wording of the messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    NotSterilized,
+    Reheating,
+    BottleReady,
+    BottleExpiring,
+    BottleExpired,
+    TimeLeft,
+    WaterTooCold,
+    MilkTooHot,
+    SensorLost,
+    SensorRestored,
+    SensorUnreliable,
+    SensorReliable,
+}
+
+impl Event {
+    pub const ALL: [Event; 12] = [
+        Event::NotSterilized,
+        Event::Reheating,
+        Event::BottleReady,
+        Event::BottleExpiring,
+        Event::BottleExpired,
+        Event::TimeLeft,
+        Event::WaterTooCold,
+        Event::MilkTooHot,
+        Event::SensorLost,
+        Event::SensorRestored,
+        Event::SensorUnreliable,
+        Event::SensorReliable,
+    ];
+
+    /// Name used in settings.
+    pub fn key(&self) -> &'static str {
+        match self {
+            Event::NotSterilized => "NOT_STERILIZED",
+            Event::Reheating => "REHEATING",
+            Event::BottleReady => "BOTTLE_READY",
+            Event::BottleExpiring => "BOTTLE_EXPIRING",
+            Event::BottleExpired => "BOTTLE_EXPIRED",
+            Event::TimeLeft => "TIME_LEFT",
+            Event::WaterTooCold => "WATER_TOO_COLD",
+            Event::MilkTooHot => "MILK_TOO_HOT",
+            Event::SensorLost => "SENSOR_LOST",
+            Event::SensorRestored => "SENSOR_RESTORED",
+            Event::SensorUnreliable => "SENSOR_UNRELIABLE",
+            Event::SensorReliable => "SENSOR_RELIABLE",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Event::NotSterilized => "Not sterilized",
+            Event::Reheating => "Reheating",
+            Event::BottleReady => "Bottle ready",
+            Event::BottleExpiring => "Bottle expiring",
+            Event::BottleExpired => "Bottle expired",
+            Event::TimeLeft => "Time left",
+            Event::WaterTooCold => "Water too cold",
+            Event::MilkTooHot => "Milk too hot",
+            Event::SensorLost => "Sensor lost",
+            Event::SensorRestored => "Sensor restored",
+            Event::SensorUnreliable => "Sensor unreliable",
+            Event::SensorReliable => "Sensor reliable",
+        }
+    }
+
+    /// Earlier events of the same sensor this one puts an end to.
+    pub fn resolves(&self) -> &'static [Event] {
+        match self {
+            Event::SensorRestored => &[Event::SensorLost],
+            Event::SensorReliable => &[Event::SensorUnreliable],
+            _ => &[],
+        }
+    }
+
+    /// True for events about the prepared bottle, over once it is used or the next
+    /// one is started.
+    pub fn is_about_the_bottle(&self) -> bool {
+        !matches!(
+            self,
+            Event::SensorLost
+                | Event::SensorRestored
+                | Event::SensorUnreliable
+                | Event::SensorReliable
+        )
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(event: &str) -> Result<Self, Self::Err> {
+        let key = event.trim().to_uppercase();
+        Event::ALL
+            .into_iter()
+            .find(|known_event| known_event.key() == key)
+            .ok_or_else(|| format!("Unknown event: {}", event))
+    }
+}
+
+/// Something the parents should know about a bottle or a probe.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub event: Event,
+    pub severity: Severity,
+    /// Short summary, e.g. for a subject line.
+    pub title: String,
+    /// Full message, what a SMS carries.
+    pub body: String,
+    pub session_id: Option<String>,
+    /// Name of the probe the notification is about.
+    pub sensor_name: Option<String>,
+}
+
+impl Notification {
+    pub fn new(event: Event, severity: Severity, body: String) -> Self {
+        Notification {
+            event,
+            severity,
+            title: event.title().to_string(),
+            body,
+            session_id: None,
+            sensor_name: None,
+        }
+    }
+
+    pub fn about(mut self, sensor_name: &str) -> Self {
+        self.sensor_name = Some(sensor_name.to_string());
+        self
+    }
+
+    pub fn for_session(mut self, session_id: Option<&str>) -> Self {
+        self.session_id = session_id.map(str::to_string);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NotificationError {
+    Delivery(String),
+}
+
+impl Display for NotificationError {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NotificationError::Delivery(message) => {
+                write!(formatter, "Unable to deliver notification: {}", message)
+            }
+        }
+    }
+}
+
+/// Outcome of the last notification sent to one recipient of a channel.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Accepted by the provider, with its message id when it gave one.
+    Sent {
+        message_id: Option<String>,
+        at: DateTime<Utc>,
+    },
+    Failed {
+        reason: String,
+        attempts: u32,
+        at: DateTime<Utc>,
+    },
+}
+
+pub type SendFuture<'a> = Pin<Box<dyn Future<Output = Result<(), NotificationError>> + Send + 'a>>;
+
+/// A channel notifications are delivered through.
+pub trait Notifier: Send + Sync {
+    /// Short channel name for logs, e.g. `sms`.
+    fn name(&self) -> &str;
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a>;
+
+    /// Last delivery status per recipient, for channels that track them.
+    fn delivery_statuses(&self) -> HashMap<String, DeliveryStatus> {
+        HashMap::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Channel {
+    Sms,
+    Log,
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel.trim().to_lowercase().as_str() {
+            "sms" => Ok(Channel::Sms),
+            "log" => Ok(Channel::Log),
+            _ => Err(format!("Unknown notification channel: {}", channel)),
+        }
+    }
+}
+
+/// A notification waiting to go through some channels.
+pub struct Delivery {
+    pub notification: Notification,
+    pub notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+/// Last delivery status per channel and recipient, e.g. `("sms", "+15550001")`.
+pub fn delivery_statuses(
+    notifiers: &[Arc<dyn Notifier>],
+) -> HashMap<(String, String), DeliveryStatus> {
+    notifiers
+        .iter()
+        .flat_map(|notifier| {
+            notifier
+                .delivery_statuses()
+                .into_iter()
+                .map(|(recipient, status)| ((notifier.name().to_string(), recipient), status))
+        })
+        .collect()
+}
+
+/// Sends to every channel of the delivery and returns how many of them delivered it.
+pub async fn deliver(delivery: &Delivery) -> usize {
+    let notification = &delivery.notification;
+    let mut delivered = 0;
+    for notifier in &delivery.notifiers {
+        match notifier.send(notification).await {
+            Ok(()) => delivered += 1,
+            Err(err) => warn!(
+                "{} notification {:?} failed: {}",
+                notifier.name(),
+                notification.title,
+                err
+            ),
+        }
+    }
+    if delivered == 0 {
+        let failed_recipients: Vec<String> = delivery_statuses(&delivery.notifiers)
+            .into_iter()
+            .filter(|(_, status)| matches!(status, DeliveryStatus::Failed { .. }))
+            .map(|((channel, recipient), _)| format!("{} {}", channel, recipient))
+            .collect();
+        error!(
+            "Notification {:?} reached nobody, failed for: {}",
+            notification.title,
+            failed_recipients.join(", ")
+        );
+    }
+    delivered
+}
+
+/// Sends the queued deliveries one after the other, so retries and slow providers
+/// never hold up the monitoring.
+async fn run_deliveries(mut deliveries: UnboundedReceiver<Delivery>) {
+    while let Some(delivery) = deliveries.recv().await {
+        deliver(&delivery).await;
+    }
+}
+
+/// Sends every notification of a profile to all of its channels, then escalates
+/// the ones nobody acknowledges. Sending happens in a background task. The
+/// escalations can be shared between profiles, so a probe switching profile still
+/// resolves the alerts raised under the previous one.
+pub struct Dispatcher {
+    label: String,
+    notifiers: Vec<Arc<dyn Notifier>>,
+    escalations: Arc<Mutex<Escalations>>,
+    deliveries: UnboundedSender<Delivery>,
+    worker: JoinHandle<()>,
+}
+
+impl Dispatcher {
+    pub fn new(label: String, notifiers: Vec<Arc<dyn Notifier>>) -> Self {
+        let (deliveries, receiver) = mpsc::unbounded_channel();
+        Dispatcher {
+            label,
+            notifiers,
+            escalations: Arc::new(Mutex::new(Escalations::default())),
+            deliveries,
+            worker: tokio::spawn(run_deliveries(receiver)),
+        }
+    }
+
+    pub fn with_escalations(mut self, escalations: Arc<Mutex<Escalations>>) -> Self {
+        self.escalations = escalations;
+        self
+    }
+
+    /// Builds the channels listed in `NOTIFICATION_CHANNELS` for the recipients of `profile`.
+    pub fn for_profile(profile: &Profile, escalations: Arc<Mutex<Escalations>>) -> Self {
+        let raw_channels: String = get_env_or_default(
+            NOTIFICATION_CHANNELS_KEY,
+            DEFAULT_NOTIFICATION_CHANNELS.to_string(),
+        );
+        let notifiers = raw_channels
+            .split(',')
+            .filter(|channel| !channel.trim().is_empty())
+            .map(|channel| -> Arc<dyn Notifier> {
+                match channel.parse().unwrap_or_else(|err| panic!("{}", err)) {
+                    Channel::Sms => Arc::new(TwilioSms::from_env(profile.recipients.clone())),
+                    Channel::Log => Arc::new(LogNotifier),
+                }
+            })
+            .collect();
+        Dispatcher::new(profile.label(), notifiers).with_escalations(escalations)
+    }
+
+    /// Queues the notification for every channel, labelled with the profile name.
+    pub fn dispatch(&self, notification: Notification) {
+        let notification = Notification {
+            body: format!("{}{}", self.label, notification.body),
+            ..notification
+        };
+        let mut escalations = self.escalations.lock().unwrap();
+        escalations.resolve(&notification);
+        escalations.raise(&notification, Utc::now());
+        drop(escalations);
+        self.queue(Delivery {
+            notification,
+            notifiers: self.notifiers.clone(),
+        });
+    }
+
+    /// Queues the escalation tiers that became due.
+    pub fn escalate(&self, now: DateTime<Utc>) {
+        let deliveries = self.escalations.lock().unwrap().escalate(now);
+        for delivery in deliveries {
+            self.queue(delivery);
+        }
+    }
+
+    /// Stops escalating the alerts about the bottle on `sensor_name`, e.g. once it
+    /// was used or the next one is started.
+    pub fn resolve_bottle(&self, sensor_name: &str) {
+        let cancelled = self
+            .escalations
+            .lock()
+            .unwrap()
+            .cancel(Some(sensor_name), |event| event.is_about_the_bottle());
+        if cancelled > 0 {
+            info!(
+                "Stopped escalating {} alerts about the bottle on {}{}",
+                cancelled, sensor_name, self.label
+            );
+        }
+    }
+
+    /// Stops escalating the alerts of `sensor_name`, or all alerts.
+    pub fn acknowledge(&self, sensor_name: Option<&str>) {
+        let cancelled = self.escalations.lock().unwrap().acknowledge(sensor_name);
+        if cancelled > 0 {
+            info!("Acknowledged {} alerts{}", cancelled, self.label);
+        }
+    }
+
+    /// Last delivery status per channel and recipient of the profile.
+    pub fn delivery_statuses(&self) -> HashMap<(String, String), DeliveryStatus> {
+        delivery_statuses(&self.notifiers)
+    }
+
+    /// Waits for the queued notifications to be sent.
+    pub async fn close(self) {
+        drop(self.deliveries);
+        if let Err(err) = self.worker.await {
+            error!("Notification delivery{} stopped: {}", self.label, err);
+        }
+    }
+
+    fn queue(&self, delivery: Delivery) {
+        if self.deliveries.send(delivery).is_err() {
+            error!(
+                "Notification delivery{} stopped, dropping a notification",
+                self.label
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::escalation::EscalationTier;
+    use crate::notifications::recording_notifier::{RecordingNotifier, RECIPIENT};
+
+    fn bottle_ready() -> Notification {
+        Notification::new(
+            Event::BottleReady,
+            Severity::Info,
+            "Bottle is ready".to_string(),
+        )
+        .for_session(Some("28-test-1"))
+    }
+
+    /// Escalations of `event` after 10 minutes, and what the tier sent.
+    fn escalations(event: Event) -> (Arc<Mutex<Escalations>>, Arc<Mutex<Vec<Notification>>>) {
+        let tier = RecordingNotifier::new("call", Ok(()));
+        let tier_sent = tier.sent();
+        let escalations = Escalations::new(HashMap::from([(
+            event,
+            vec![EscalationTier {
+                after: chrono::Duration::minutes(10),
+                notifier: Arc::new(tier),
+            }],
+        )]));
+        (Arc::new(Mutex::new(escalations)), tier_sent)
+    }
+
+    fn dispatcher(label: &str, escalations: Arc<Mutex<Escalations>>) -> Dispatcher {
+        Dispatcher::new(
+            label.to_string(),
+            vec![Arc::new(RecordingNotifier::new("sms", Ok(())))],
+        )
+        .with_escalations(escalations)
+    }
+
+    /// A dispatcher escalating `event` after 10 minutes, and what the tier sent.
+    fn escalating_dispatcher(event: Event) -> (Dispatcher, Arc<Mutex<Vec<Notification>>>) {
+        let (escalations, tier_sent) = escalations(event);
+        (dispatcher("", escalations), tier_sent)
+    }
+
+    #[tokio::test]
+    async fn dispatch_fans_out_to_every_channel() {
+        let sms = RecordingNotifier::new("sms", Ok(()));
+        let log = RecordingNotifier::new("log", Ok(()));
+        let (sms_sent, log_sent) = (sms.sent(), log.sent());
+        let dispatcher =
+            Dispatcher::new("[night] ".to_string(), vec![Arc::new(sms), Arc::new(log)]);
+
+        dispatcher.dispatch(bottle_ready());
+        dispatcher.close().await;
+
+        for sent in [sms_sent, log_sent] {
+            assert_eq!(sent.lock().unwrap()[0].body, "[night] Bottle is ready");
+        }
+    }
+
+    #[tokio::test]
+    async fn delivery_statuses_are_kept_per_channel_and_recipient() {
+        let notifiers: Vec<Arc<dyn Notifier>> = vec![
+            Arc::new(RecordingNotifier::new("sms", Ok(()))),
+            Arc::new(RecordingNotifier::new(
+                "call",
+                Err(NotificationError::Delivery("offline".to_string())),
+            )),
+        ];
+
+        let delivered = deliver(&Delivery {
+            notification: bottle_ready(),
+            notifiers: notifiers.clone(),
+        })
+        .await;
+
+        assert_eq!(delivered, 1);
+        let statuses = delivery_statuses(&notifiers);
+        assert!(matches!(
+            statuses[&("sms".to_string(), RECIPIENT.to_string())],
+            DeliveryStatus::Sent { .. }
+        ));
+        assert!(matches!(
+            statuses[&("call".to_string(), RECIPIENT.to_string())],
+            DeliveryStatus::Failed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn dispatcher_reports_the_delivery_statuses_of_its_channels() {
+        let dispatcher = Dispatcher::new(
+            String::new(),
+            vec![Arc::new(RecordingNotifier::new(
+                "sms",
+                Err(NotificationError::Delivery("offline".to_string())),
+            ))],
+        );
+        assert!(dispatcher.delivery_statuses().is_empty());
+
+        dispatcher.dispatch(bottle_ready());
+        while dispatcher.delivery_statuses().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        assert!(matches!(
+            dispatcher.delivery_statuses()[&("sms".to_string(), RECIPIENT.to_string())],
+            DeliveryStatus::Failed { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn resolving_notification_cancels_the_escalation() {
+        let (dispatcher, tier_sent) = escalating_dispatcher(Event::SensorLost);
+        let about = |event| Notification::new(event, Severity::Info, String::new()).about("left");
+
+        dispatcher.dispatch(about(Event::SensorLost));
+        dispatcher.dispatch(about(Event::SensorRestored));
+        dispatcher.escalate(Utc::now() + chrono::Duration::minutes(30));
+        dispatcher.close().await;
+
+        assert!(tier_sent.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn used_bottle_cancels_its_escalations() {
+        let (dispatcher, tier_sent) = escalating_dispatcher(Event::WaterTooCold);
+        let too_cold = |sensor_name| {
+            Notification::new(Event::WaterTooCold, Severity::Warning, String::new())
+                .about(sensor_name)
+        };
+
+        dispatcher.dispatch(too_cold("left"));
+        dispatcher.dispatch(too_cold("right"));
+        dispatcher.resolve_bottle("left");
+        dispatcher.escalate(Utc::now() + chrono::Duration::minutes(30));
+        dispatcher.close().await;
+
+        let tier_sent = tier_sent.lock().unwrap();
+        assert_eq!(tier_sent.len(), 1);
+        assert_eq!(tier_sent[0].sensor_name.as_deref(), Some("right"));
+    }
+
+    #[tokio::test]
+    async fn bottle_resolved_under_another_profile_is_not_escalated() {
+        let (escalations, tier_sent) = escalations(Event::BottleExpired);
+        let formula = dispatcher("[formula] ", escalations.clone());
+        let breast_milk = dispatcher("[breast_milk] ", escalations);
+
+        formula.dispatch(
+            Notification::new(Event::BottleExpired, Severity::Warning, String::new()).about("left"),
+        );
+        breast_milk.resolve_bottle("left");
+        formula.escalate(Utc::now() + chrono::Duration::minutes(30));
+        breast_milk.escalate(Utc::now() + chrono::Duration::minutes(30));
+        formula.close().await;
+        breast_milk.close().await;
+
+        assert!(tier_sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn events_are_named_by_their_setting_key() {
+        assert_eq!("water_too_cold".parse(), Ok(Event::WaterTooCold));
+        assert!("kettle_on".parse::<Event>().is_err());
+        for event in Event::ALL {
+            assert_eq!(event.key().parse(), Ok(event));
+        }
+    }
+
+    #[test]
+    fn unknown_channel_is_rejected() {
+        assert_eq!("SMS".parse(), Ok(Channel::Sms));
+        assert!("pager".parse::<Channel>().is_err());
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+
+use crate::notifications::{DeliveryStatus, Notification, NotificationError, Notifier, SendFuture};
+
+/// The one recipient every recording notifier sends to.
+pub const RECIPIENT: &str = "+15550001";
+
+/// Keeps what it was asked to send, since mockall cannot return the borrowed future.
+pub struct RecordingNotifier {
+    name: &'static str,
+    result: Result<(), NotificationError>,
+    sent: Arc<Mutex<Vec<Notification>>>,
+    delivery_statuses: Mutex<HashMap<String, DeliveryStatus>>,
+}
+
+impl RecordingNotifier {
+    pub fn new(name: &'static str, result: Result<(), NotificationError>) -> Self {
+        RecordingNotifier {
+            name,
+            result,
+            sent: Arc::new(Mutex::new(Vec::new())),
+            delivery_statuses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Shares the log of sent notifications, to read it once the notifier is boxed.
+    pub fn sent(&self) -> Arc<Mutex<Vec<Notification>>> {
+        self.sent.clone()
+    }
+}
+
+impl Notifier for RecordingNotifier {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        self.sent.lock().unwrap().push(notification.clone());
+        let status = match &self.result {
+            Ok(()) => DeliveryStatus::Sent {
+                message_id: None,
+                at: Utc::now(),
+            },
+            Err(err) => DeliveryStatus::Failed {
+                reason: err.to_string(),
+                attempts: 1,
+                at: Utc::now(),
+            },
+        };
+        self.delivery_statuses
+            .lock()
+            .unwrap()
+            .insert(RECIPIENT.to_string(), status);
+        Box::pin(async move { self.result.clone() })
+    }
+
+    fn delivery_statuses(&self) -> HashMap<String, DeliveryStatus> {
+        self.delivery_statuses.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+
+use log::debug;
+use twilio::OutboundCall;
+
+use crate::notifications::twilio_sms::{
+    classify, send_with_retries, twilio_client_from_env, RetryPolicy,
+};
+use crate::notifications::{DeliveryStatus, Notification, NotificationError, Notifier, SendFuture};
+
+static TWILIO_CALL_TWIML_URL_KEY: &str = "TWILIO_CALL_TWIML_URL";
+
+/// Rings every recipient through Twilio, the call plays the TwiML found at
+/// `TWILIO_CALL_TWIML_URL` since a phone call cannot carry the notification text.
+pub struct TwilioCall {
+    client: twilio::Client,
+    from_phone_number: String,
+    twiml_url: String,
+    to_phone_numbers: Vec<String>,
+    retry_policy: RetryPolicy,
+    delivery_statuses: Mutex<HashMap<String, DeliveryStatus>>,
+}
+
+impl TwilioCall {
+    pub fn from_env(to_phone_numbers: Vec<String>) -> Self {
+        let (client, from_phone_number) = twilio_client_from_env();
+        let twiml_url = env::var(TWILIO_CALL_TWIML_URL_KEY)
+            .ok()
+            .filter(|twiml_url| !twiml_url.is_empty())
+            .unwrap_or_else(|| panic!("{} must be set for calls", TWILIO_CALL_TWIML_URL_KEY));
+        TwilioCall {
+            client,
+            from_phone_number,
+            twiml_url,
+            to_phone_numbers,
+            retry_policy: RetryPolicy::from_env(),
+            delivery_statuses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn call_all(&self) -> Result<(), NotificationError> {
+        let mut failures = Vec::new();
+        for to_phone_number in &self.to_phone_numbers {
+            let status = send_with_retries(self.retry_policy, "Call", to_phone_number, || async {
+                let response = self
+                    .client
+                    .make_call(OutboundCall::new(
+                        &self.from_phone_number,
+                        to_phone_number,
+                        &self.twiml_url,
+                    ))
+                    .await;
+                debug!("Response: {:?}", response);
+                classify(response.map(|call| call.sid))
+            })
+            .await;
+            if let DeliveryStatus::Failed { reason, .. } = &status {
+                failures.push(format!("{}: {}", to_phone_number, reason));
+            }
+            self.delivery_statuses
+                .lock()
+                .unwrap()
+                .insert(to_phone_number.clone(), status);
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(NotificationError::Delivery(failures.join(", ")))
+        }
+    }
+}
+
+impl Notifier for TwilioCall {
+    fn name(&self) -> &str {
+        "call"
+    }
+
+    fn send<'a>(&'a self, _notification: &'a Notification) -> SendFuture<'a> {
+        Box::pin(self.call_all())
+    }
+
+    fn delivery_statuses(&self) -> HashMap<String, DeliveryStatus> {
+        self.delivery_statuses.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{debug, error, warn};
+use twilio::{OutboundMessage, TwilioError};
+
+use crate::helpers::get_env_or_default;
+use crate::notifications::{DeliveryStatus, Notification, NotificationError, Notifier, SendFuture};
+
+static TWILIO_ACCOUNT_ID_KEY: &str = "TWILIO_ACCOUNT_ID";
+static TWILIO_AUTH_TOKEN_KEY: &str = "TWILIO_AUTH_TOKEN";
+static FROM_PHONE_NUMBER_KEY: &str = "FROM_PHONE_NUMBER";
+static SMS_MAXIMUM_ATTEMPTS_KEY: &str = "SMS_MAXIMUM_ATTEMPTS";
+static SMS_RETRY_BACKOFF_IN_MILLISECONDS_KEY: &str = "SMS_RETRY_BACKOFF_IN_MILLISECONDS";
+
+const DEFAULT_SMS_MAXIMUM_ATTEMPTS: u32 = 3;
+const DEFAULT_SMS_RETRY_BACKOFF_IN_MILLISECONDS: u64 = 1000;
+
+const TOO_MANY_REQUESTS: u16 = 429;
+
+/// How many times a SMS is tried, waiting `initial_backoff` then twice as long
+/// after every transient failure.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub maximum_attempts: u32,
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> Self {
+        RetryPolicy {
+            maximum_attempts: get_env_or_default(
+                SMS_MAXIMUM_ATTEMPTS_KEY,
+                DEFAULT_SMS_MAXIMUM_ATTEMPTS,
+            )
+            .max(1),
+            initial_backoff: Duration::from_millis(get_env_or_default(
+                SMS_RETRY_BACKOFF_IN_MILLISECONDS_KEY,
+                DEFAULT_SMS_RETRY_BACKOFF_IN_MILLISECONDS,
+            )),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            maximum_attempts: DEFAULT_SMS_MAXIMUM_ATTEMPTS,
+            initial_backoff: Duration::from_millis(DEFAULT_SMS_RETRY_BACKOFF_IN_MILLISECONDS),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SendFailure {
+    /// Worth trying again: Twilio unreachable, rate limiting or a Twilio outage.
+    Transient(String),
+    /// Trying again would fail the same way, e.g. a bad token or number.
+    Permanent(String),
+}
+
+/// Returns the Twilio message or call id, if the response could be read.
+pub fn classify(response: Result<String, TwilioError>) -> Result<Option<String>, SendFailure> {
+    match response {
+        Ok(sid) => Ok(Some(sid)),
+        // Twilio accepted the request but its answer could not be decoded.
+        Err(TwilioError::ParsingError) => Ok(None),
+        Err(TwilioError::HTTPError(status))
+            if status.as_u16() == TOO_MANY_REQUESTS || status.is_server_error() =>
+        {
+            Err(SendFailure::Transient(status.to_string()))
+        }
+        Err(TwilioError::NetworkError(err)) if err.is_connect() || err.is_timeout() => {
+            Err(SendFailure::Transient(err.to_string()))
+        }
+        // The request may have reached Twilio, e.g. the answer to an accepted message
+        // was cut, sending it again could deliver it twice.
+        Err(TwilioError::NetworkError(err)) => {
+            warn!("Sent, status unknown: {}", err);
+            Ok(None)
+        }
+        Err(err) => Err(SendFailure::Permanent(err.to_string())),
+    }
+}
+
+/// Calls `attempt` until it succeeds, fails permanently or runs out of attempts,
+/// and returns the resulting status.
+pub async fn send_with_retries<F, Fut>(
+    policy: RetryPolicy,
+    channel: &str,
+    recipient: &str,
+    mut attempt: F,
+) -> DeliveryStatus
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<String>, SendFailure>>,
+{
+    let mut backoff = policy.initial_backoff;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let failure = match attempt().await {
+            Ok(message_id) => {
+                return DeliveryStatus::Sent {
+                    message_id,
+                    at: Utc::now(),
+                }
+            }
+            Err(failure) => failure,
+        };
+        match failure {
+            SendFailure::Transient(reason) if attempts < policy.maximum_attempts => {
+                warn!(
+                    "{} to {} failed ({}), attempt {} of {}, retrying in {:?}",
+                    channel, recipient, reason, attempts, policy.maximum_attempts, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            SendFailure::Transient(reason) | SendFailure::Permanent(reason) => {
+                error!(
+                    "{} to {} failed after {} attempts: {}",
+                    channel, recipient, attempts, reason
+                );
+                return DeliveryStatus::Failed {
+                    reason,
+                    attempts,
+                    at: Utc::now(),
+                };
+            }
+        }
+    }
+}
+
+/// Twilio client and sender number from the Twilio settings, shared by SMS and calls.
+pub fn twilio_client_from_env() -> (twilio::Client, String) {
+    let twilio_account_id = env::var(TWILIO_ACCOUNT_ID_KEY)
+        .unwrap_or_else(|_| panic!("{} must be set", TWILIO_ACCOUNT_ID_KEY));
+    let twilio_auth_token = env::var(TWILIO_AUTH_TOKEN_KEY)
+        .unwrap_or_else(|_| panic!("{} must be set", TWILIO_AUTH_TOKEN_KEY));
+    let from_phone_number = env::var(FROM_PHONE_NUMBER_KEY)
+        .unwrap_or_else(|_| panic!("{} must be set", FROM_PHONE_NUMBER_KEY));
+    (
+        twilio::Client::new(&twilio_account_id, &twilio_auth_token),
+        from_phone_number,
+    )
+}
+
+/// Sends the notification body by SMS to every recipient through Twilio.
+pub struct TwilioSms {
+    client: twilio::Client,
+    from_phone_number: String,
+    to_phone_numbers: Vec<String>,
+    retry_policy: RetryPolicy,
+    delivery_statuses: Mutex<HashMap<String, DeliveryStatus>>,
+}
+
+impl TwilioSms {
+    pub fn from_env(to_phone_numbers: Vec<String>) -> Self {
+        let (client, from_phone_number) = twilio_client_from_env();
+        TwilioSms {
+            client,
+            from_phone_number,
+            to_phone_numbers,
+            retry_policy: RetryPolicy::from_env(),
+            delivery_statuses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn send_to_all(&self, notification: &Notification) -> Result<(), NotificationError> {
+        let mut failures = Vec::new();
+        for to_phone_number in &self.to_phone_numbers {
+            let status = send_with_retries(self.retry_policy, "SMS", to_phone_number, || async {
+                let response = self
+                    .client
+                    .send_message(OutboundMessage::new(
+                        &self.from_phone_number,
+                        to_phone_number,
+                        &notification.body,
+                    ))
+                    .await;
+                debug!("Response: {:?}", response);
+                classify(response.map(|message| message.sid))
+            })
+            .await;
+            if let DeliveryStatus::Failed { reason, .. } = &status {
+                failures.push(format!("{}: {}", to_phone_number, reason));
+            }
+            self.delivery_statuses
+                .lock()
+                .unwrap()
+                .insert(to_phone_number.clone(), status);
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(NotificationError::Delivery(failures.join(", ")))
+        }
+    }
+}
+
+impl Notifier for TwilioSms {
+    fn name(&self) -> &str {
+        "sms"
+    }
+
+    fn send<'a>(&'a self, notification: &'a Notification) -> SendFuture<'a> {
+        Box::pin(self.send_to_all(notification))
+    }
+
+    fn delivery_statuses(&self) -> HashMap<String, DeliveryStatus> {
+        self.delivery_statuses.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn quick_retries() -> RetryPolicy {
+        RetryPolicy {
+            maximum_attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+        }
+    }
+
+    async fn send_with(
+        results: Vec<Result<Option<String>, SendFailure>>,
+    ) -> (DeliveryStatus, usize) {
+        let results = RefCell::new(results.into_iter());
+        let calls = RefCell::new(0);
+        let status = send_with_retries(quick_retries(), "SMS", "+15550001", || {
+            *calls.borrow_mut() += 1;
+            let result = results.borrow_mut().next().unwrap();
+            async move { result }
+        })
+        .await;
+        (status, calls.into_inner())
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let (status, calls) = send_with(vec![
+            Err(SendFailure::Transient(
+                "503 Service Unavailable".to_string(),
+            )),
+            Err(SendFailure::Transient("429 Too Many Requests".to_string())),
+            Ok(Some("SM123".to_string())),
+        ])
+        .await;
+
+        assert_eq!(calls, 3);
+        assert!(matches!(
+            status,
+            DeliveryStatus::Sent { message_id: Some(message_id), .. } if message_id == "SM123"
+        ));
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let (status, calls) = send_with(vec![Err(SendFailure::Permanent(
+            "Invalid HTTP status code: 401".to_string(),
+        ))])
+        .await;
+
+        assert_eq!(calls, 1);
+        assert!(matches!(status, DeliveryStatus::Failed { attempts: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn retries_stop_after_the_maximum_attempts() {
+        let (status, calls) =
+            send_with(vec![Err(SendFailure::Transient("timeout".to_string())); 3]).await;
+
+        assert_eq!(calls, 3);
+        assert!(matches!(status, DeliveryStatus::Failed { attempts: 3, .. }));
+    }
+
+    #[test]
+    fn twilio_errors_are_classified() {
+        assert!(matches!(
+            classify(Err(TwilioError::AuthError)),
+            Err(SendFailure::Permanent(_))
+        ));
+        assert!(matches!(
+            classify(Err(TwilioError::BadRequest)),
+            Err(SendFailure::Permanent(_))
+        ));
+        assert_eq!(classify(Err(TwilioError::ParsingError)), Ok(None));
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::env;
+
+use crate::devices::water_temperature_sensor::TemperatureThreshold;
+use crate::helpers::parse_key_value_list;
+use crate::session::{ExpiryPolicy, SterilizationPolicy};
+
+pub static DEFAULT_PROFILE_NAME: &str = "default";
+
+static PROFILES_KEY: &str = "PROFILES";
+static SENSOR_PROFILES_KEY: &str = "SENSOR_PROFILES";
+static TO_PHONE_NUMBERS_KEY: &str = "TO_PHONE_NUMBERS";
+
+/// Everything needed to prepare one kind of bottle, e.g. formula or warmed breast milk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub temperature_threshold: TemperatureThreshold,
+    pub expiry_policy: ExpiryPolicy,
+    pub sterilization_policy: SterilizationPolicy,
+    pub recipients: Vec<String>,
+}
+
+impl Profile {
+    /// The default profile reads the plain settings, a named one reads the settings
+    /// prefixed with `PROFILE_<NAME>_` and only shares the recipients when it has none.
+    pub fn from_env(name: &str, default_recipients: &[String]) -> Self {
+        let prefix = profile_prefix(name);
+        let recipients = parse_phone_numbers(
+            &env::var(format!("{}{}", prefix, TO_PHONE_NUMBERS_KEY)).unwrap_or_default(),
+        );
+        Profile {
+            name: name.to_string(),
+            temperature_threshold: TemperatureThreshold::from_env(&prefix),
+            expiry_policy: ExpiryPolicy::from_env(&prefix),
+            sterilization_policy: SterilizationPolicy::from_env(&prefix),
+            recipients: if recipients.is_empty() {
+                default_recipients.to_vec()
+            } else {
+                recipients
+            },
+        }
+    }
+
+    /// Prefix for SMS so the reader knows which recipe the message is about.
+    pub fn label(&self) -> String {
+        if self.name == DEFAULT_PROFILE_NAME {
+            String::new()
+        } else {
+            format!("[{}] ", self.name)
+        }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: DEFAULT_PROFILE_NAME.to_string(),
+            temperature_threshold: TemperatureThreshold::default(),
+            expiry_policy: ExpiryPolicy::default(),
+            sterilization_policy: SterilizationPolicy::default(),
+            recipients: Vec::new(),
+        }
+    }
+}
+
+fn profile_prefix(name: &str) -> String {
+    if name == DEFAULT_PROFILE_NAME {
+        return String::new();
+    }
+    let normalized: String = name
+        .chars()
+        .map(|character| {
+            if character.is_ascii_alphanumeric() {
+                character.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("PROFILE_{}_", normalized)
+}
+
+pub fn parse_phone_numbers(raw_phone_numbers: &str) -> Vec<String> {
+    raw_phone_numbers
+        .split(',')
+        .map(|phone_number| phone_number.trim().to_string())
+        .filter(|phone_number| !phone_number.is_empty())
+        .collect()
+}
+
+/// Loads the default profile followed by the ones listed in `PROFILES`.
+pub fn load_profiles() -> Vec<Profile> {
+    let default_recipients =
+        parse_phone_numbers(&env::var(TO_PHONE_NUMBERS_KEY).expect("TO_PHONE_NUMBERS must be set"));
+    let mut profiles = vec![Profile::from_env(DEFAULT_PROFILE_NAME, &default_recipients)];
+    for name in env::var(PROFILES_KEY).unwrap_or_default().split(',') {
+        let name = name.trim();
+        if name.is_empty() || profiles.iter().any(|profile| profile.name == name) {
+            continue;
+        }
+        profiles.push(Profile::from_env(name, &default_recipients));
+    }
+    profiles
+}
+
+pub fn find_profile<'a>(profiles: &'a [Profile], name: &str) -> Option<&'a Profile> {
+    profiles.iter().find(|profile| profile.name == name)
+}
+
+/// Profile names per sensor name or serial, from `SENSOR_PROFILES`.
+pub fn get_sensor_profiles() -> HashMap<String, String> {
+    env::var(SENSOR_PROFILES_KEY)
+        .map(|sensor_profiles| parse_key_value_list(&sensor_profiles))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::water_temperature_sensor::PreparationMode;
+    use chrono::Duration;
+
+    #[test]
+    fn test_profile_prefix() {
+        assert_eq!(profile_prefix(DEFAULT_PROFILE_NAME), "");
+        assert_eq!(profile_prefix("breast-milk"), "PROFILE_BREAST_MILK_");
+    }
+
+    #[test]
+    fn named_profile_reads_its_own_settings() {
+        env::set_var("PROFILE_PROFILE_TEST_MILK_PREPARATION_MODE", "warming");
+        env::set_var("PROFILE_PROFILE_TEST_MILK_FORMULA_EXPIRY_IN_MINUTES", "60");
+        env::set_var(
+            "PROFILE_PROFILE_TEST_MILK_TO_PHONE_NUMBERS",
+            "+15550001, +15550002",
+        );
+
+        let profile = Profile::from_env("profile_test_milk", &["+15559999".to_string()]);
+
+        assert_eq!(profile.temperature_threshold.mode, PreparationMode::Warming);
+        assert_eq!(profile.temperature_threshold.target, 37.0);
+        assert_eq!(profile.expiry_policy.expires_after, Duration::minutes(60));
+        assert_eq!(profile.recipients, vec!["+15550001", "+15550002"]);
+        assert_eq!(profile.label(), "[profile_test_milk] ");
+    }
+
+    #[test]
+    fn named_profile_without_recipients_uses_the_default_ones() {
+        let profile = Profile::from_env("profile_test_night", &["+15559999".to_string()]);
+
+        assert_eq!(profile.recipients, vec!["+15559999"]);
+        assert_eq!(
+            profile.temperature_threshold,
+            TemperatureThreshold::default()
+        );
+    }
+}
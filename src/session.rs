@@ -0,0 +1,987 @@
+use core::fmt::Formatter;
+use std::fmt::Display;
+
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::Serialize;
+
+use crate::devices::water_temperature_sensor::{PreparationMode, WaterTemperatureSensor};
+use crate::helpers::get_prefixed_env_or_default;
+use crate::profile::Profile;
+
+/// How far below its peak the water must drop before a hot session counts as cooling.
+const COOLING_DETECTION_DROP: f32 = 0.5;
+/// How far above its lowest point of the last few minutes the milk must rise, and
+/// for how long, before it counts as warming. A room slowly drifting warmer is
+/// too slow to ever rise that much within the window.
+const WARMING_DETECTION_RISE: f32 = 0.5;
+const WARMING_DETECTION_WINDOW_IN_SECONDS: i64 = 300;
+const WARMING_CONFIRMATION_IN_SECONDS: i64 = 5;
+/// How far above its lowest point cooling water must rise, and for how long, before
+/// it counts as being reheated.
+const REHEATING_DETECTION_RISE: f32 = 1.0;
+const REHEATING_CONFIRMATION_IN_SECONDS: i64 = 10;
+
+static STERILIZATION_TEMPERATURE_KEY: &str = "STERILIZATION_TEMPERATURE";
+static STERILIZATION_MINIMUM_IN_SECONDS_KEY: &str = "STERILIZATION_MINIMUM_IN_SECONDS";
+static FORMULA_EXPIRY_IN_MINUTES_KEY: &str = "FORMULA_EXPIRY_IN_MINUTES";
+static FORMULA_EXPIRY_REMINDER_IN_MINUTES_KEY: &str = "FORMULA_EXPIRY_REMINDER_IN_MINUTES";
+
+const DEFAULT_STERILIZATION_TEMPERATURE: f32 = 70.0;
+const DEFAULT_STERILIZATION_MINIMUM_IN_SECONDS: i64 = 0;
+const DEFAULT_FORMULA_EXPIRY_IN_MINUTES: i64 = 120;
+const DEFAULT_FORMULA_EXPIRY_REMINDER_IN_MINUTES: i64 = 15;
+
+/// The water must stay at or above `temperature` for `minimum_duration` to count as sterilized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SterilizationPolicy {
+    pub temperature: f32,
+    pub minimum_duration: Duration,
+}
+
+impl SterilizationPolicy {
+    pub fn from_env(prefix: &str) -> Self {
+        SterilizationPolicy {
+            temperature: get_prefixed_env_or_default(
+                prefix,
+                STERILIZATION_TEMPERATURE_KEY,
+                DEFAULT_STERILIZATION_TEMPERATURE,
+            ),
+            minimum_duration: Duration::seconds(get_prefixed_env_or_default(
+                prefix,
+                STERILIZATION_MINIMUM_IN_SECONDS_KEY,
+                DEFAULT_STERILIZATION_MINIMUM_IN_SECONDS,
+            )),
+        }
+    }
+}
+
+impl Default for SterilizationPolicy {
+    fn default() -> Self {
+        SterilizationPolicy {
+            temperature: DEFAULT_STERILIZATION_TEMPERATURE,
+            minimum_duration: Duration::seconds(DEFAULT_STERILIZATION_MINIMUM_IN_SECONDS),
+        }
+    }
+}
+
+/// What the session saw of the sterilization phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SterilizationRecord {
+    pub reached_at: Option<DateTime<Utc>>,
+    pub time_above: Duration,
+    above_since: Option<DateTime<Utc>>,
+}
+
+impl SterilizationRecord {
+    fn record(&mut self, temperature: f32, sterilization_temperature: f32, now: DateTime<Utc>) {
+        if temperature < sterilization_temperature {
+            self.above_since = None;
+            return;
+        }
+        if self.reached_at.is_none() {
+            self.reached_at = Some(now);
+        }
+        if let Some(above_since) = self.above_since {
+            self.time_above += now - above_since;
+        }
+        self.above_since = Some(now);
+    }
+}
+
+/// How long prepared formula stays safe once the water is ready.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExpiryPolicy {
+    pub expires_after: Duration,
+    pub reminder_before: Duration,
+}
+
+impl ExpiryPolicy {
+    pub fn from_env(prefix: &str) -> Self {
+        ExpiryPolicy {
+            expires_after: Duration::minutes(get_prefixed_env_or_default(
+                prefix,
+                FORMULA_EXPIRY_IN_MINUTES_KEY,
+                DEFAULT_FORMULA_EXPIRY_IN_MINUTES,
+            )),
+            reminder_before: Duration::minutes(get_prefixed_env_or_default(
+                prefix,
+                FORMULA_EXPIRY_REMINDER_IN_MINUTES_KEY,
+                DEFAULT_FORMULA_EXPIRY_REMINDER_IN_MINUTES,
+            )),
+        }
+    }
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        ExpiryPolicy {
+            expires_after: Duration::minutes(DEFAULT_FORMULA_EXPIRY_IN_MINUTES),
+            reminder_before: Duration::minutes(DEFAULT_FORMULA_EXPIRY_REMINDER_IN_MINUTES),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Idle,
+    Hot,
+    Cooling,
+    Warming,
+    Ready,
+    Expired,
+    Consumed,
+}
+
+impl Display for SessionState {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionState::Idle => write!(formatter, "idle"),
+            SessionState::Hot => write!(formatter, "hot"),
+            SessionState::Cooling => write!(formatter, "cooling"),
+            SessionState::Warming => write!(formatter, "warming"),
+            SessionState::Ready => write!(formatter, "ready"),
+            SessionState::Expired => write!(formatter, "expired"),
+            SessionState::Consumed => write!(formatter, "consumed"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionReason {
+    Heated,
+    StartedCooling,
+    StartedWarming,
+    Reheated,
+    ProbeReturned,
+    ReachedTarget,
+    ExpiryElapsed,
+    MarkedConsumed,
+}
+
+impl Display for TransitionReason {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransitionReason::Heated => write!(formatter, "water heated above the target"),
+            TransitionReason::StartedCooling => write!(formatter, "water started cooling"),
+            TransitionReason::StartedWarming => write!(formatter, "milk started warming"),
+            TransitionReason::Reheated => write!(formatter, "water is being reheated"),
+            TransitionReason::ProbeReturned => write!(formatter, "probe is back in the water"),
+            TransitionReason::ReachedTarget => write!(formatter, "water reached the target"),
+            TransitionReason::ExpiryElapsed => {
+                write!(formatter, "prepared bottle passed its safe window")
+            }
+            TransitionReason::MarkedConsumed => write!(formatter, "bottle marked as used"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionTransition {
+    pub session_id: String,
+    pub from: SessionState,
+    pub to: SessionState,
+    pub reason: TransitionReason,
+    pub at: DateTime<Utc>,
+}
+
+/// Tracks one bottle from boiling (or from the fridge) to being used, per sensor.
+pub struct BottleSession {
+    sensor_identity: String,
+    profile_name: String,
+    id: Option<String>,
+    state: SessionState,
+    entered_at: DateTime<Utc>,
+    reason: Option<TransitionReason>,
+    peak_temperature: f32,
+    trough_temperature: f32,
+    trough_at: DateTime<Utc>,
+    rising_since: Option<DateTime<Utc>>,
+    probe_out: bool,
+    time_left_announced: bool,
+    expiry_policy: ExpiryPolicy,
+    expiry_reminded: bool,
+    sterilization_policy: SterilizationPolicy,
+    sterilization: SterilizationRecord,
+    sterilization_warned: bool,
+    too_cold_alerted: bool,
+    overshoot_alerted: bool,
+}
+
+impl BottleSession {
+    pub fn new(sensor_identity: String, profile: &Profile) -> Self {
+        BottleSession {
+            sensor_identity,
+            profile_name: profile.name.clone(),
+            expiry_policy: profile.expiry_policy,
+            expiry_reminded: false,
+            sterilization_policy: profile.sterilization_policy,
+            sterilization: SterilizationRecord::default(),
+            sterilization_warned: false,
+            too_cold_alerted: false,
+            overshoot_alerted: false,
+            id: None,
+            state: SessionState::Idle,
+            entered_at: Utc::now(),
+            reason: None,
+            peak_temperature: f32::MIN,
+            trough_temperature: f32::MAX,
+            trough_at: DateTime::<Utc>::UNIX_EPOCH,
+            rising_since: None,
+            probe_out: false,
+            time_left_announced: false,
+        }
+    }
+
+    pub fn profile_name(&self) -> &str {
+        &self.profile_name
+    }
+
+    /// True while a bottle is being brought to its target temperature.
+    pub fn is_in_progress(&self) -> bool {
+        matches!(
+            self.state,
+            SessionState::Hot | SessionState::Cooling | SessionState::Warming
+        )
+    }
+
+    /// Switches to another profile for the next bottle, refused while one is in progress.
+    pub fn select_profile(&mut self, profile: &Profile) -> bool {
+        if self.is_in_progress() {
+            return false;
+        }
+        self.profile_name = profile.name.clone();
+        self.expiry_policy = profile.expiry_policy;
+        self.sterilization_policy = profile.sterilization_policy;
+        true
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub fn entered_at(&self) -> DateTime<Utc> {
+        self.entered_at
+    }
+
+    pub fn reason(&self) -> Option<TransitionReason> {
+        self.reason
+    }
+
+    pub fn sterilization(&self) -> SterilizationRecord {
+        self.sterilization
+    }
+
+    pub fn sterilization_policy(&self) -> SterilizationPolicy {
+        self.sterilization_policy
+    }
+
+    /// True once the water held the sterilization temperature long enough.
+    pub fn is_sterilized(&self) -> bool {
+        self.sterilization.reached_at.is_some()
+            && self.sterilization.time_above >= self.sterilization_policy.minimum_duration
+    }
+
+    /// Moves the session forward from the latest sensor state.
+    pub fn update(
+        &mut self,
+        water_temperature_sensor: &WaterTemperatureSensor,
+        now: DateTime<Utc>,
+    ) -> Option<SessionTransition> {
+        match water_temperature_sensor.mode() {
+            PreparationMode::Cooling => self.update_cooling(water_temperature_sensor, now),
+            PreparationMode::Warming => self.update_warming(water_temperature_sensor, now),
+        }
+    }
+
+    fn update_cooling(
+        &mut self,
+        water_temperature_sensor: &WaterTemperatureSensor,
+        now: DateTime<Utc>,
+    ) -> Option<SessionTransition> {
+        let temperature = water_temperature_sensor.current_temperature;
+        let armed = water_temperature_sensor.is_armed();
+        let probe_removed = water_temperature_sensor.is_probe_removed();
+        if matches!(self.state, SessionState::Hot | SessionState::Cooling) {
+            self.sterilization
+                .record(temperature, self.sterilization_policy.temperature, now);
+            self.probe_out |= probe_removed;
+        }
+
+        match self.state {
+            // The probe went back into the bottle it was pulled out of, rather than
+            // into a new one.
+            SessionState::Ready if armed && self.probe_out => {
+                self.resume_after_probe_out(temperature);
+                Some(self.transition(SessionState::Cooling, TransitionReason::ProbeReturned, now))
+            }
+            SessionState::Idle
+            | SessionState::Ready
+            | SessionState::Expired
+            | SessionState::Consumed
+                if armed =>
+            {
+                self.start(now);
+                self.peak_temperature = temperature;
+                self.sterilization
+                    .record(temperature, self.sterilization_policy.temperature, now);
+                Some(self.transition(SessionState::Hot, TransitionReason::Heated, now))
+            }
+            SessionState::Hot | SessionState::Cooling if !armed => {
+                Some(self.transition(SessionState::Ready, TransitionReason::ReachedTarget, now))
+            }
+            SessionState::Hot => {
+                self.peak_temperature = self.peak_temperature.max(temperature);
+                if temperature < self.peak_temperature - COOLING_DETECTION_DROP {
+                    self.trough_temperature = temperature;
+                    self.rising_since = None;
+                    Some(self.transition(
+                        SessionState::Cooling,
+                        TransitionReason::StartedCooling,
+                        now,
+                    ))
+                } else {
+                    None
+                }
+            }
+            SessionState::Cooling if self.probe_out && !probe_removed => {
+                self.resume_after_probe_out(temperature);
+                None
+            }
+            SessionState::Cooling => {
+                if self.is_reheating(temperature, now) {
+                    self.peak_temperature = temperature;
+                    self.time_left_announced = false;
+                    Some(self.transition(SessionState::Hot, TransitionReason::Reheated, now))
+                } else {
+                    None
+                }
+            }
+            SessionState::Ready if now >= self.expires_at() => {
+                Some(self.transition(SessionState::Expired, TransitionReason::ExpiryElapsed, now))
+            }
+            _ => None,
+        }
+    }
+
+    fn update_warming(
+        &mut self,
+        water_temperature_sensor: &WaterTemperatureSensor,
+        now: DateTime<Utc>,
+    ) -> Option<SessionTransition> {
+        let temperature = water_temperature_sensor.current_temperature;
+        let armed = water_temperature_sensor.is_armed();
+
+        match self.state {
+            // Ready milk left out cools down and re-arms the sensor, it must still expire.
+            SessionState::Ready if now >= self.expires_at() => {
+                Some(self.transition(SessionState::Expired, TransitionReason::ExpiryElapsed, now))
+            }
+            SessionState::Idle
+            | SessionState::Ready
+            | SessionState::Expired
+            | SessionState::Consumed
+                if armed =>
+            {
+                if self.is_warming(temperature, now) {
+                    self.start(now);
+                    Some(self.transition(
+                        SessionState::Warming,
+                        TransitionReason::StartedWarming,
+                        now,
+                    ))
+                } else {
+                    None
+                }
+            }
+            SessionState::Warming if !armed => {
+                Some(self.transition(SessionState::Ready, TransitionReason::ReachedTarget, now))
+            }
+            _ => None,
+        }
+    }
+
+    /// True once cooling water stayed clearly above its lowest point for a while,
+    /// e.g. because the kettle was switched back on.
+    fn is_reheating(&mut self, temperature: f32, now: DateTime<Utc>) -> bool {
+        self.trough_temperature = self.trough_temperature.min(temperature);
+        if temperature <= self.trough_temperature + REHEATING_DETECTION_RISE {
+            self.rising_since = None;
+            return false;
+        }
+        let rising_since = *self.rising_since.get_or_insert(now);
+        now - rising_since >= Duration::seconds(REHEATING_CONFIRMATION_IN_SECONDS)
+    }
+
+    /// Forgets the readings taken out of the water, they would look like reheating.
+    fn resume_after_probe_out(&mut self, temperature: f32) {
+        self.probe_out = false;
+        self.peak_temperature = temperature;
+        self.trough_temperature = temperature;
+        self.rising_since = None;
+    }
+
+    /// True once the milk rose clearly above its lowest point of the last few minutes
+    /// and stayed there for a while, e.g. because the bottle was put in warm water.
+    fn is_warming(&mut self, temperature: f32, now: DateTime<Utc>) -> bool {
+        if temperature <= self.trough_temperature
+            || now - self.trough_at > Duration::seconds(WARMING_DETECTION_WINDOW_IN_SECONDS)
+        {
+            self.trough_temperature = temperature;
+            self.trough_at = now;
+        }
+        if temperature <= self.trough_temperature + WARMING_DETECTION_RISE {
+            self.rising_since = None;
+            return false;
+        }
+        let rising_since = *self.rising_since.get_or_insert(now);
+        now - rising_since >= Duration::seconds(WARMING_CONFIRMATION_IN_SECONDS)
+    }
+
+    /// Stops the expiry timer of a ready bottle because it was used.
+    pub fn mark_consumed(&mut self, now: DateTime<Utc>) -> Option<SessionTransition> {
+        if self.state != SessionState::Ready {
+            return None;
+        }
+        Some(self.transition(
+            SessionState::Consumed,
+            TransitionReason::MarkedConsumed,
+            now,
+        ))
+    }
+
+    /// Returns the time left before expiry the first time the reminder is due.
+    pub fn take_expiry_reminder(&mut self, now: DateTime<Utc>) -> Option<Duration> {
+        if self.state != SessionState::Ready || self.expiry_reminded {
+            return None;
+        }
+        let time_left = self.expires_at() - now;
+        if time_left > self.expiry_policy.reminder_before {
+            return None;
+        }
+        self.expiry_reminded = true;
+        Some(time_left)
+    }
+
+    /// Returns true the first time the water leaves Hot without ever reaching the
+    /// sterilization temperature, reheating does not repeat the warning.
+    pub fn take_sterilization_warning(&mut self, transition: &SessionTransition) -> bool {
+        if transition.from != SessionState::Hot
+            || self.sterilization.reached_at.is_some()
+            || self.sterilization_warned
+        {
+            return false;
+        }
+        self.sterilization_warned = true;
+        true
+    }
+
+    /// Returns how long the water has been ready the first time it gets too cold
+    /// before the bottle was used.
+    pub fn take_too_cold_alert(
+        &mut self,
+        water_temperature_sensor: &WaterTemperatureSensor,
+        now: DateTime<Utc>,
+    ) -> Option<Duration> {
+        if self.state != SessionState::Ready
+            || self.too_cold_alerted
+            || !water_temperature_sensor.is_too_cold()
+            || water_temperature_sensor.is_probe_removed()
+        {
+            return None;
+        }
+        self.too_cold_alerted = true;
+        Some(now - self.entered_at)
+    }
+
+    /// Returns true the first time warmed milk goes above the maximum safe temperature.
+    pub fn take_overshoot_alert(
+        &mut self,
+        water_temperature_sensor: &WaterTemperatureSensor,
+    ) -> bool {
+        if !matches!(self.state, SessionState::Warming | SessionState::Ready)
+            || self.overshoot_alerted
+            || !water_temperature_sensor.is_overshooting()
+        {
+            return false;
+        }
+        self.overshoot_alerted = true;
+        true
+    }
+
+    fn expires_at(&self) -> DateTime<Utc> {
+        self.entered_at + self.expiry_policy.expires_after
+    }
+
+    /// Returns true the first time it is called while the session is cooling or warming.
+    pub fn take_time_left_announcement(&mut self) -> bool {
+        if !matches!(self.state, SessionState::Cooling | SessionState::Warming)
+            || self.time_left_announced
+        {
+            return false;
+        }
+        self.time_left_announced = true;
+        true
+    }
+
+    fn start(&mut self, now: DateTime<Utc>) {
+        self.id = Some(format!("{}-{}", self.sensor_identity, now.timestamp()));
+        self.time_left_announced = false;
+        self.expiry_reminded = false;
+        self.sterilization = SterilizationRecord::default();
+        self.sterilization_warned = false;
+        self.too_cold_alerted = false;
+        self.overshoot_alerted = false;
+        self.trough_temperature = f32::MAX;
+        self.rising_since = None;
+        self.probe_out = false;
+    }
+
+    fn transition(
+        &mut self,
+        to: SessionState,
+        reason: TransitionReason,
+        at: DateTime<Utc>,
+    ) -> SessionTransition {
+        let transition = SessionTransition {
+            session_id: self.id.clone().unwrap_or_default(),
+            from: self.state,
+            to,
+            reason,
+            at,
+        };
+        info!(
+            "Session {}: {} -> {} ({})",
+            transition.session_id, transition.from, transition.to, transition.reason
+        );
+        self.state = to;
+        self.entered_at = at;
+        self.reason = Some(reason);
+        transition
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::plausibility::PlausibilityPolicy;
+    use crate::devices::poller::SensorSample;
+    use crate::devices::reading_filter::ReadingFilter;
+    use crate::devices::temperature_source::TemperatureReading;
+    use crate::devices::water_temperature_sensor::TemperatureThreshold;
+    use chrono::Duration;
+
+    struct Bench {
+        sensor: WaterTemperatureSensor,
+        session: BottleSession,
+        seconds: i64,
+    }
+
+    impl Bench {
+        fn new() -> Self {
+            Bench::with_threshold(TemperatureThreshold::default())
+        }
+
+        fn with_threshold(threshold: TemperatureThreshold) -> Self {
+            let profile = Profile {
+                temperature_threshold: threshold,
+                sterilization_policy: SterilizationPolicy {
+                    temperature: 70.0,
+                    minimum_duration: Duration::seconds(2),
+                },
+                ..Profile::default()
+            };
+            Bench {
+                sensor: WaterTemperatureSensor::new(
+                    "bottle".to_string(),
+                    "28-test".to_string(),
+                    profile.temperature_threshold,
+                    PlausibilityPolicy {
+                        maximum_slew_rate_per_second: f32::MAX,
+                        ..PlausibilityPolicy::default()
+                    },
+                    ReadingFilter::None,
+                ),
+                session: BottleSession::new("28-test".to_string(), &profile),
+                seconds: 0,
+            }
+        }
+
+        fn now(&self) -> DateTime<Utc> {
+            DateTime::<Utc>::UNIX_EPOCH + Duration::seconds(self.seconds)
+        }
+
+        /// Cools down slowly enough for the probe to look like it stays in the water.
+        fn cool_to(&mut self, temperature: f32) {
+            while self.sensor.current_temperature > temperature {
+                self.feed((self.sensor.current_temperature - 0.1).max(temperature));
+            }
+        }
+
+        fn feed(&mut self, temperature: f32) -> Option<SessionTransition> {
+            self.seconds += 1;
+            let at = self.now();
+            self.sensor.update(SensorSample {
+                identity: "28-test".to_string(),
+                reading: Ok(TemperatureReading {
+                    temperature,
+                    timestamp: at,
+                }),
+                fault_count: 0,
+                attached: true,
+            });
+            self.session.update(&self.sensor, at)
+        }
+    }
+
+    #[test]
+    fn session_walks_from_idle_to_ready() {
+        let mut bench = Bench::new();
+        assert_eq!(bench.feed(22.0), None);
+        assert_eq!(bench.session.state(), SessionState::Idle);
+
+        let hot = bench.feed(95.0).unwrap();
+        assert_eq!(
+            (hot.from, hot.to, hot.reason),
+            (
+                SessionState::Idle,
+                SessionState::Hot,
+                TransitionReason::Heated
+            )
+        );
+        assert_eq!(hot.session_id, "28-test-2");
+
+        assert_eq!(bench.feed(95.2), None);
+        let cooling = bench.feed(94.0).unwrap();
+        assert_eq!(cooling.to, SessionState::Cooling);
+        assert_eq!(bench.session.entered_at(), cooling.at);
+
+        assert_eq!(bench.feed(45.0), None);
+        let ready = bench.feed(29.5).unwrap();
+        assert_eq!(
+            (ready.from, ready.to, ready.reason),
+            (
+                SessionState::Cooling,
+                SessionState::Ready,
+                TransitionReason::ReachedTarget
+            )
+        );
+        assert_eq!(ready.session_id, hot.session_id);
+        assert_eq!(bench.feed(30.5), None);
+    }
+
+    #[test]
+    fn heating_a_ready_bottle_starts_a_new_session() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        bench.cool_to(29.0);
+        assert_eq!(bench.session.state(), SessionState::Ready);
+        let first_session = bench.session.id().unwrap().to_string();
+
+        let hot = bench.feed(90.0).unwrap();
+        assert_eq!(hot.to, SessionState::Hot);
+        assert_ne!(hot.session_id, first_session);
+    }
+
+    #[test]
+    fn time_left_is_announced_once_per_cooling_session() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        assert!(!bench.session.take_time_left_announcement());
+
+        bench.feed(90.0);
+        assert!(bench.session.take_time_left_announcement());
+        assert!(!bench.session.take_time_left_announcement());
+    }
+
+    #[test]
+    fn ready_bottle_gets_a_reminder_then_expires() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        bench.feed(29.0);
+        assert_eq!(bench.session.take_expiry_reminder(bench.now()), None);
+
+        bench.seconds += 105 * 60;
+        let time_left = bench.session.take_expiry_reminder(bench.now()).unwrap();
+        assert_eq!(time_left, Duration::minutes(15));
+        assert_eq!(bench.session.take_expiry_reminder(bench.now()), None);
+
+        bench.seconds += 15 * 60;
+        let expired = bench.feed(28.0).unwrap();
+        assert_eq!(
+            (expired.from, expired.to, expired.reason),
+            (
+                SessionState::Ready,
+                SessionState::Expired,
+                TransitionReason::ExpiryElapsed
+            )
+        );
+    }
+
+    #[test]
+    fn consumed_bottle_cancels_the_expiry() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        assert_eq!(bench.session.mark_consumed(bench.now()), None);
+
+        bench.feed(29.0);
+        let consumed = bench.session.mark_consumed(bench.now()).unwrap();
+        assert_eq!(consumed.to, SessionState::Consumed);
+
+        bench.seconds += 3 * 60 * 60;
+        assert_eq!(bench.session.take_expiry_reminder(bench.now()), None);
+        assert_eq!(bench.feed(25.0), None);
+        assert_eq!(bench.session.state(), SessionState::Consumed);
+    }
+
+    #[test]
+    fn sterilization_time_is_recorded_while_hot() {
+        let mut bench = Bench::new();
+        bench.feed(60.0);
+        bench.feed(71.0);
+        bench.feed(80.0);
+        assert!(!bench.session.is_sterilized());
+
+        bench.feed(72.0);
+        bench.feed(65.0);
+        assert!(bench.session.is_sterilized());
+        assert_eq!(
+            bench.session.sterilization().reached_at,
+            Some(bench.now() - Duration::seconds(3))
+        );
+        assert_eq!(
+            bench.session.sterilization().time_above,
+            Duration::seconds(2)
+        );
+    }
+
+    #[test]
+    fn session_cooling_without_sterilization_is_flagged() {
+        let mut bench = Bench::new();
+        bench.feed(50.0);
+        bench.feed(45.0);
+        assert_eq!(bench.session.state(), SessionState::Cooling);
+        assert!(!bench.session.is_sterilized());
+        assert_eq!(bench.session.sterilization().reached_at, None);
+    }
+
+    #[test]
+    fn sterilization_warning_is_sent_once_per_session() {
+        let mut bench = Bench::new();
+        bench.feed(50.0);
+        bench.feed(49.8);
+        let cooling = bench.feed(49.4).unwrap();
+        assert!(bench.session.take_sterilization_warning(&cooling));
+
+        bench.feed(49.0);
+        for _ in 0..11 {
+            bench.feed(51.0);
+        }
+        assert_eq!(bench.session.state(), SessionState::Hot);
+        let cooling_again = bench.feed(50.4).unwrap();
+        assert_eq!(cooling_again.to, SessionState::Cooling);
+        assert!(!bench.session.take_sterilization_warning(&cooling_again));
+    }
+
+    #[test]
+    fn too_cold_alert_fires_once_while_ready() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        assert_eq!(
+            bench
+                .session
+                .take_too_cold_alert(&bench.sensor, bench.now()),
+            None
+        );
+
+        bench.cool_to(29.0);
+        assert_eq!(bench.session.state(), SessionState::Ready);
+        assert_eq!(
+            bench
+                .session
+                .take_too_cold_alert(&bench.sensor, bench.now()),
+            None
+        );
+
+        bench.seconds += 600;
+        bench.feed(24.5);
+        assert_eq!(
+            bench
+                .session
+                .take_too_cold_alert(&bench.sensor, bench.now()),
+            Some(Duration::seconds(611))
+        );
+        assert_eq!(
+            bench
+                .session
+                .take_too_cold_alert(&bench.sensor, bench.now()),
+            None
+        );
+    }
+
+    #[test]
+    fn warming_session_alerts_on_the_way_up() {
+        let mut bench = Bench::with_threshold(TemperatureThreshold::warming());
+        assert_eq!(bench.feed(5.0), None);
+        assert_eq!(bench.feed(4.8), None);
+        assert_eq!(bench.session.state(), SessionState::Idle);
+
+        for temperature in [6.0, 6.5, 7.0, 7.5, 8.0] {
+            assert_eq!(bench.feed(temperature), None);
+        }
+        let warming = bench.feed(8.5).unwrap();
+        assert_eq!(
+            (warming.from, warming.to, warming.reason),
+            (
+                SessionState::Idle,
+                SessionState::Warming,
+                TransitionReason::StartedWarming
+            )
+        );
+        assert!(bench.session.take_time_left_announcement());
+
+        assert_eq!(bench.feed(36.5), None);
+        let ready = bench.feed(37.2).unwrap();
+        assert_eq!(
+            (ready.from, ready.to, ready.reason),
+            (
+                SessionState::Warming,
+                SessionState::Ready,
+                TransitionReason::ReachedTarget
+            )
+        );
+        assert!(!bench.session.is_sterilized());
+        assert!(!bench.session.take_overshoot_alert(&bench.sensor));
+
+        bench.feed(40.5);
+        assert!(bench.session.take_overshoot_alert(&bench.sensor));
+        assert!(!bench.session.take_overshoot_alert(&bench.sensor));
+    }
+
+    #[test]
+    fn room_drifting_warmer_does_not_start_warming() {
+        let mut bench = Bench::with_threshold(TemperatureThreshold::warming());
+        for minute in 0..=60 {
+            assert_eq!(bench.feed(20.0 + 0.01 * minute as f32), None);
+            bench.seconds += 59;
+        }
+        assert_eq!(bench.session.state(), SessionState::Idle);
+    }
+
+    #[test]
+    fn warmed_milk_left_to_cool_still_expires() {
+        let mut bench = Bench::with_threshold(TemperatureThreshold::warming());
+        for temperature in [5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0] {
+            bench.feed(temperature);
+        }
+        assert_eq!(bench.session.state(), SessionState::Warming);
+        bench.feed(37.2);
+        assert_eq!(bench.session.state(), SessionState::Ready);
+
+        assert_eq!(bench.feed(35.0), None);
+        assert!(bench.sensor.is_armed());
+
+        bench.seconds += 120 * 60;
+        let expired = bench.feed(30.0).unwrap();
+        assert_eq!(
+            (expired.from, expired.to, expired.reason),
+            (
+                SessionState::Ready,
+                SessionState::Expired,
+                TransitionReason::ExpiryElapsed
+            )
+        );
+    }
+
+    #[test]
+    fn profile_can_only_change_between_bottles() {
+        let mut bench = Bench::new();
+        let night = Profile {
+            name: "night".to_string(),
+            expiry_policy: ExpiryPolicy {
+                expires_after: Duration::minutes(60),
+                reminder_before: Duration::minutes(10),
+            },
+            ..Profile::default()
+        };
+
+        bench.feed(95.0);
+        assert!(!bench.session.select_profile(&night));
+        assert_eq!(bench.session.profile_name(), "default");
+
+        bench.feed(29.0);
+        assert!(bench.session.select_profile(&night));
+        assert_eq!(bench.session.profile_name(), "night");
+
+        bench.seconds += 50 * 60;
+        assert_eq!(
+            bench.session.take_expiry_reminder(bench.now()),
+            Some(Duration::minutes(10))
+        );
+    }
+
+    #[test]
+    fn sustained_rise_while_cooling_is_reheating() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        bench.cool_to(80.0);
+        assert_eq!(bench.session.state(), SessionState::Cooling);
+        assert!(bench.session.take_time_left_announcement());
+        let session_id = bench.session.id().unwrap().to_string();
+
+        bench.cool_to(78.0);
+        assert_eq!(bench.feed(79.1), None);
+        assert_eq!(bench.feed(79.0), None);
+        for _ in 0..10 {
+            assert_eq!(bench.feed(79.5), None);
+        }
+        let reheated = bench.feed(82.0).unwrap();
+        assert_eq!(
+            (reheated.from, reheated.to, reheated.reason),
+            (
+                SessionState::Cooling,
+                SessionState::Hot,
+                TransitionReason::Reheated
+            )
+        );
+        assert_eq!(reheated.session_id, session_id);
+
+        bench.feed(81.0);
+        assert_eq!(bench.session.state(), SessionState::Cooling);
+        assert!(bench.session.take_time_left_announcement());
+    }
+
+    #[test]
+    fn probe_put_back_resumes_the_session() {
+        let mut bench = Bench::new();
+        bench.feed(95.0);
+        bench.cool_to(60.0);
+        assert_eq!(bench.session.state(), SessionState::Cooling);
+        let session_id = bench.session.id().unwrap().to_string();
+
+        let ready = bench.feed(24.0).unwrap();
+        assert_eq!(ready.to, SessionState::Ready);
+        assert!(bench.sensor.is_probe_removed());
+
+        let resumed = bench.feed(59.5).unwrap();
+        assert_eq!(
+            (resumed.from, resumed.to, resumed.reason),
+            (
+                SessionState::Ready,
+                SessionState::Cooling,
+                TransitionReason::ProbeReturned
+            )
+        );
+        assert_eq!(resumed.session_id, session_id);
+
+        bench.cool_to(50.0);
+        assert_eq!(bench.session.state(), SessionState::Cooling);
+        assert_eq!(bench.session.id(), Some(session_id.as_str()));
+    }
+}